@@ -0,0 +1,665 @@
+extern crate base32;
+extern crate base64;
+extern crate hex;
+extern crate scrypt;
+extern crate serde_json;
+
+use super::algorithm::Algorithm;
+use super::config::{Profile, ProfileType, ValidationError};
+use super::crypto;
+use super::import;
+use super::otpauth;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+// Aegis のエクスポートファイルの version
+const VERSION: u32 = 1;
+// db の version
+const DB_VERSION: u32 = 3;
+// エクスポートする db の version
+const EXPORT_DB_VERSION: u32 = 2;
+
+// Steam Guard のコードの桁数
+const STEAM_DIGITS: u8 = 5;
+
+// パスワードで鍵を保護するスロット
+const SLOT_PASSWORD: u8 = 1;
+
+// Aegis と同じ scrypt のパラメーター (N = 2^15)
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+const SALT_LENGTH: usize = 32;
+// 受け付ける scrypt のパラメーター。scrypt は 128 * r * N バイトを確保するので
+// 範囲外の値のスロットは壊れたものとして扱う
+const SCRYPT_LOG_N_RANGE: std::ops::RangeInclusive<u32> = 1..=18;
+const SCRYPT_R_RANGE: std::ops::RangeInclusive<u32> = 1..=8;
+const SCRYPT_P_RANGE: std::ops::RangeInclusive<u32> = 1..=16;
+
+// Aegis の "Export" で出力される JSON
+//
+// db is an object in a plain export, or a base64 string encrypted with the master key.
+// The master key is encrypted in each of the slots.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Vault {
+    version: u32,
+    header: Header,
+    db: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Header {
+    slots: Option<Vec<Slot>>,
+    params: Option<KeyParams>,
+}
+
+// A key slot which holds the master key encrypted with a key derived by scrypt.
+#[derive(Serialize, Deserialize, Debug)]
+struct Slot {
+    #[serde(rename = "type")]
+    slot_type: u8,
+    #[serde(default)]
+    uuid: String,
+    key: String,
+    key_params: KeyParams,
+    n: Option<u64>,
+    r: Option<u32>,
+    p: Option<u32>,
+    salt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repaired: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_backup: Option<bool>,
+}
+
+// AES-GCM の nonce と tag (hex)
+#[derive(Serialize, Deserialize, Debug)]
+struct KeyParams {
+    nonce: String,
+    tag: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Database {
+    version: u32,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Entry {
+    #[serde(rename = "type")]
+    entry_type: String,
+    #[serde(default)]
+    uuid: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    note: String,
+    #[serde(default)]
+    icon: Option<String>,
+    info: Info,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Info {
+    #[serde(default)]
+    secret: String,
+    #[serde(default)]
+    algo: String,
+    digits: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    period: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counter: Option<u64>,
+}
+
+impl Vault {
+    // Parse an Aegis export.
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let vault: Self = match serde_json::from_str(data) {
+            Ok(vault) => vault,
+            Err(_) => return Err(String::from("It is not an Aegis export.")),
+        };
+        if vault.version > VERSION {
+            return Err(format!(
+                "Unsupported Aegis export version: {}",
+                vault.version
+            ));
+        }
+
+        Ok(vault)
+    }
+
+    // Build a plain export.
+    pub fn plain(profiles: &[&Profile]) -> Result<Self, String> {
+        let database = Database::from_profiles(profiles)?;
+        let db = match serde_json::to_value(database) {
+            Ok(db) => db,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        Ok(Self {
+            version: VERSION,
+            header: Header {
+                slots: None,
+                params: None,
+            },
+            db,
+        })
+    }
+
+    // Check whether the profile can be exported.
+    pub fn validate_profile(profile: &Profile) -> Result<(), ValidationError> {
+        Entry::from_profile(profile).map(|_| ())
+    }
+
+    // Build an export which is encrypted with the password.
+    pub fn encrypt(profiles: &[&Profile], password: &str) -> Result<Self, String> {
+        Self::encrypt_with(profiles, password, SCRYPT_LOG_N)
+    }
+
+    fn encrypt_with(profiles: &[&Profile], password: &str, log_n: u8) -> Result<Self, String> {
+        let database = Database::from_profiles(profiles)?;
+        let plaintext = match serde_json::to_vec(&database) {
+            Ok(plaintext) => plaintext,
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let master_key = crypto::random_bytes(crypto::KEY_LENGTH);
+        let slot = Slot::lock(&master_key, password, log_n)?;
+        let (ciphertext, params) = encrypt(&master_key, &plaintext)?;
+
+        Ok(Self {
+            version: VERSION,
+            header: Header {
+                slots: Some(vec![slot]),
+                params: Some(params),
+            },
+            db: serde_json::Value::String(BASE64.encode(ciphertext)),
+        })
+    }
+
+    // Format as JSON.
+    pub fn to_json(&self) -> Result<String, String> {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => Ok(json),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    // Whether the database is encrypted with a password.
+    pub fn is_encrypted(&self) -> bool {
+        self.db.is_string()
+    }
+
+    // Get the database.
+    //
+    // The password is required only when the export is encrypted.
+    pub fn database(&self, password: Option<&str>) -> Result<Database, String> {
+        let db = match &self.db {
+            serde_json::Value::String(encrypted) => {
+                let password = match password {
+                    Some(password) => password,
+                    None => return Err(String::from("The password is required.")),
+                };
+                self.decrypt(encrypted, password)?
+            }
+            db => db.clone(),
+        };
+
+        let database: Database = match serde_json::from_value(db) {
+            Ok(database) => database,
+            Err(err) => return Err(format!("The database is broken: {}", err)),
+        };
+        if database.version > DB_VERSION {
+            return Err(format!(
+                "Unsupported Aegis database version: {}",
+                database.version
+            ));
+        }
+
+        Ok(database)
+    }
+
+    fn decrypt(&self, encrypted: &str, password: &str) -> Result<serde_json::Value, String> {
+        let (slots, params) = match (&self.header.slots, &self.header.params) {
+            (Some(slots), Some(params)) => (slots, params),
+            _ => {
+                return Err(String::from(
+                    "The header of the encrypted export is broken.",
+                ))
+            }
+        };
+
+        let master_key = slots
+            .iter()
+            .filter(|slot| slot.slot_type == SLOT_PASSWORD)
+            .find_map(|slot| slot.unlock(password).ok());
+        let master_key = match master_key {
+            Some(master_key) => master_key,
+            None => return Err(String::from("The password is incorrect.")),
+        };
+
+        let ciphertext = match BASE64.decode(encrypted) {
+            Ok(ciphertext) => ciphertext,
+            Err(_) => return Err(String::from("The database is broken.")),
+        };
+        let plaintext = decrypt(&master_key, params, &ciphertext)?;
+
+        match serde_json::from_slice(&plaintext) {
+            Ok(db) => Ok(db),
+            Err(_) => Err(String::from("The database is broken.")),
+        }
+    }
+}
+
+impl Slot {
+    // Build a password slot which holds the master key.
+    fn lock(master_key: &[u8], password: &str, log_n: u8) -> Result<Self, String> {
+        let salt = crypto::random_bytes(SALT_LENGTH);
+        let key = derive_key(password, &salt, log_n, SCRYPT_R, SCRYPT_P)?;
+        let (encrypted_key, key_params) = encrypt(&key, master_key)?;
+
+        Ok(Self {
+            slot_type: SLOT_PASSWORD,
+            uuid: generate_uuid(),
+            key: hex::encode(encrypted_key),
+            key_params,
+            n: Some(1 << log_n),
+            r: Some(SCRYPT_R),
+            p: Some(SCRYPT_P),
+            salt: Some(hex::encode(salt)),
+            repaired: Some(true),
+            is_backup: Some(false),
+        })
+    }
+
+    // Decrypt the master key with the password.
+    fn unlock(&self, password: &str) -> Result<Vec<u8>, String> {
+        let (n, r, p, salt) = match (self.n, self.r, self.p, &self.salt) {
+            (Some(n), Some(r), Some(p), Some(salt)) => (n, r, p, decode_hex(salt)?),
+            _ => return Err(String::from("The password slot is broken.")),
+        };
+        if !n.is_power_of_two()
+            || !SCRYPT_LOG_N_RANGE.contains(&n.trailing_zeros())
+            || !SCRYPT_R_RANGE.contains(&r)
+            || !SCRYPT_P_RANGE.contains(&p)
+        {
+            return Err(String::from("The password slot is broken."));
+        }
+
+        let key = derive_key(password, &salt, n.trailing_zeros() as u8, r, p)?;
+
+        decrypt(&key, &self.key_params, &decode_hex(&self.key)?)
+    }
+}
+
+impl Database {
+    fn from_profiles(profiles: &[&Profile]) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for profile in profiles {
+            match Entry::from_profile(profile) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => return Err(format!("{}: {}", profile.get_name(), err)),
+            }
+        }
+
+        Ok(Self {
+            version: EXPORT_DB_VERSION,
+            entries,
+        })
+    }
+
+    pub fn get_entries(&self) -> &Vec<Entry> {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<Entry> {
+        self.entries
+    }
+}
+
+impl Entry {
+    // Build an entry from a profile.
+    //
+    // The account is used as the name of the entry. The profile name is used if it is unknown.
+    pub fn from_profile(profile: &Profile) -> Result<Self, ValidationError> {
+        if profile.get_t0() != 0 {
+            return Err(ValidationError::Unsupported("T0 must be 0."));
+        }
+        let secret = match profile.get_secret() {
+            Some(secret) => base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret),
+            None => {
+                return Err(ValidationError::InvalidFormat(
+                    "Secret must be encoded in base32.",
+                ))
+            }
+        };
+
+        let (digits, period, counter) = match profile.get_type() {
+            ProfileType::Totp => (profile.get_digits(), Some(profile.get_period()), None),
+            ProfileType::Hotp => (profile.get_digits(), None, Some(profile.get_counter())),
+            ProfileType::Steam => (STEAM_DIGITS, Some(profile.get_period()), None),
+        };
+
+        Ok(Self {
+            entry_type: profile.get_type().to_string(),
+            uuid: generate_uuid(),
+            name: profile
+                .get_account()
+                .unwrap_or(profile.get_name())
+                .to_string(),
+            issuer: profile.get_issuer().unwrap_or_default().to_string(),
+            note: profile.get_notes().unwrap_or_default().to_string(),
+            icon: None,
+            info: Info {
+                secret,
+                algo: profile.get_algorithm().to_string(),
+                digits: Some(digits),
+                period,
+                counter,
+            },
+        })
+    }
+}
+
+impl import::Entry for Entry {
+    // A label to show the entry without the secret. "Issuer:Name" or "Name"
+    fn label(&self) -> String {
+        if self.issuer.is_empty() {
+            return self.name.clone();
+        }
+
+        format!("{}:{}", self.issuer, self.name)
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the issuer and the name like otpauth URI.
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        let profile_type = match self.entry_type.parse::<ProfileType>() {
+            Ok(profile_type) => profile_type,
+            Err(_) => {
+                return Err(ValidationError::Unsupported(
+                    "Type must be totp, hotp or steam.",
+                ))
+            }
+        };
+        let algorithm = match self.info.algo.parse::<Algorithm>() {
+            Ok(algorithm) => algorithm,
+            Err(_) => {
+                return Err(ValidationError::Unsupported(
+                    "Algorithm must be SHA1, SHA256 or SHA512.",
+                ))
+            }
+        };
+        if self.info.secret.is_empty() {
+            return Err(ValidationError::Requires("Secret must be present."));
+        }
+
+        let name = import::profile_name(&self.issuer, &self.name);
+        let mut profile = Profile::new(&name, &otpauth::normalize_secret(&self.info.secret)?);
+        profile.set_issuer(&self.issuer);
+        profile.set_account(&self.name);
+        profile.set_notes(&self.note);
+        profile.set_type(profile_type);
+        profile.set_algorithm(algorithm);
+        import::set_digits(&mut profile, self.info.digits);
+        if let Some(period) = self.info.period {
+            profile.set_period(period);
+        }
+        if let Some(counter) = self.info.counter {
+            profile.set_counter(counter);
+        }
+        Ok(profile)
+    }
+}
+
+// scrypt で鍵を導出する
+fn derive_key(password: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<Vec<u8>, String> {
+    let params = match scrypt::Params::new(log_n, r, p, crypto::KEY_LENGTH) {
+        Ok(params) => params,
+        Err(err) => return Err(err.to_string()),
+    };
+    let mut key = vec![0u8; crypto::KEY_LENGTH];
+    if let Err(err) = scrypt::scrypt(password.as_bytes(), salt, &params, &mut key) {
+        return Err(err.to_string());
+    }
+
+    Ok(key)
+}
+
+// AES-256-GCM で暗号化する
+//
+// It returns the ciphertext without the tag, and the nonce and the tag.
+fn encrypt(key: &[u8], plaintext: &[u8]) -> Result<(Vec<u8>, KeyParams), String> {
+    let nonce = crypto::random_bytes(crypto::NONCE_LENGTH);
+
+    let mut ciphertext = crypto::aes_gcm_encrypt(key, &nonce, plaintext)?;
+    let tag = ciphertext.split_off(ciphertext.len() - crypto::TAG_LENGTH);
+
+    Ok((
+        ciphertext,
+        KeyParams {
+            nonce: hex::encode(nonce),
+            tag: hex::encode(tag),
+        },
+    ))
+}
+
+// AES-256-GCM で復号する
+fn decrypt(key: &[u8], params: &KeyParams, ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = decode_hex(&params.nonce)?;
+    let mut payload = ciphertext.to_vec();
+    payload.extend(decode_hex(&params.tag)?);
+
+    crypto::aes_gcm_decrypt(key, &nonce, &payload)
+}
+
+// UUID version 4
+fn generate_uuid() -> String {
+    let mut bytes = crypto::random_bytes(16);
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, String> {
+    match hex::decode(value) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(String::from("The export contains a broken hex value.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    const PLAIN: &str = include_str!("../tests/fixtures/aegis-plain.json");
+    // encrypted with the password "test"
+    const ENCRYPTED: &str = include_str!("../tests/fixtures/aegis-encrypted.json");
+
+    #[test]
+    fn parse_plain_export() {
+        let vault = Vault::parse(PLAIN).unwrap();
+        assert!(!vault.is_encrypted());
+
+        let database = vault.database(None).unwrap();
+        let profiles: Vec<Result<Profile, ValidationError>> = database
+            .get_entries()
+            .iter()
+            .map(|entry| entry.to_profile())
+            .collect();
+
+        let mut totp = Profile::new("Example-alice@exampl", "JBSWY3DPEHPK3PXP");
+        totp.set_issuer("Example");
+        totp.set_account("alice@example.com");
+        totp.set_algorithm(Algorithm::Sha256);
+        totp.set_digits(8);
+        totp.set_period(60);
+        let mut hotp = Profile::new("bob", "GEZDGNBVGY3TQOJQ");
+        hotp.set_account("bob");
+        hotp.set_type(ProfileType::Hotp);
+        hotp.set_counter(5);
+        let mut steam = Profile::new("Steam-carol", "JBSWY3DPEHPK3PXP");
+        steam.set_issuer("Steam");
+        steam.set_account("carol");
+        steam.set_type(ProfileType::Steam);
+
+        assert_eq!(
+            profiles,
+            vec![
+                Ok(totp),
+                Ok(hotp),
+                Ok(steam),
+                Err(ValidationError::Unsupported(
+                    "Type must be totp, hotp or steam."
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn decrypt_encrypted_export() {
+        let vault = Vault::parse(ENCRYPTED).unwrap();
+        assert!(vault.is_encrypted());
+
+        let database = vault.database(Some("test")).unwrap();
+        let labels: Vec<String> = database
+            .get_entries()
+            .iter()
+            .map(|entry| entry.label())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Example:alice@example.com",
+                "bob",
+                "Steam:carol",
+                "Example:dave"
+            ]
+        );
+    }
+
+    #[test]
+    fn decrypt_with_wrong_password() {
+        let vault = Vault::parse(ENCRYPTED).unwrap();
+
+        assert_eq!(
+            vault.database(Some("wrong")).unwrap_err(),
+            "The password is incorrect."
+        );
+        assert_eq!(
+            vault.database(None).unwrap_err(),
+            "The password is required."
+        );
+    }
+
+    #[test]
+    fn unlock_slot_with_too_large_params() {
+        let master_key = crypto::random_bytes(crypto::KEY_LENGTH);
+        let slot = Slot::lock(&master_key, "secret", 10).unwrap();
+        assert_eq!(slot.unlock("secret").unwrap(), master_key);
+
+        for (n, r, p) in [
+            (1 << 40, SCRYPT_R, SCRYPT_P),
+            (1 << 10, 1024, SCRYPT_P),
+            (1 << 10, SCRYPT_R, 1024),
+        ] {
+            let slot = Slot {
+                n: Some(n),
+                r: Some(r),
+                p: Some(p),
+                ..Slot::lock(&master_key, "secret", 10).unwrap()
+            };
+            assert_eq!(
+                slot.unlock("secret").unwrap_err(),
+                "The password slot is broken."
+            );
+        }
+    }
+
+    #[test]
+    fn encrypt_round_trip() {
+        let mut totp = Profile::new("alice", "JBSWY3DPEHPK3PXP");
+        totp.set_account("alice");
+        let mut hotp = Profile::new("bob", "GEZDGNBVGY3TQOJQ");
+        hotp.set_account("bob");
+        hotp.set_type(ProfileType::Hotp);
+        hotp.set_algorithm(Algorithm::Sha512);
+        hotp.set_digits(8);
+        hotp.set_counter(42);
+        let mut steam = Profile::new("carol", "JBSWY3DPEHPK3PXP");
+        steam.set_account("carol");
+        steam.set_type(ProfileType::Steam);
+
+        let vault = Vault::encrypt_with(&[&totp, &hotp, &steam], "secret", 10).unwrap();
+        let vault = Vault::parse(&vault.to_json().unwrap()).unwrap();
+        assert!(vault.is_encrypted());
+        assert!(vault.database(Some("wrong")).is_err());
+
+        let profiles: Vec<Profile> = vault
+            .database(Some("secret"))
+            .unwrap()
+            .get_entries()
+            .iter()
+            .map(|entry| entry.to_profile().unwrap())
+            .collect();
+        assert_eq!(profiles, vec![totp, hotp, steam]);
+    }
+
+    #[test]
+    fn plain_round_trip() {
+        let mut profile = Profile::new("Example-alice", "JBSWY3DPEHPK3PXP");
+        profile.set_issuer("Example");
+        profile.set_account("alice");
+        profile.set_notes("recovery codes are in the safe");
+
+        let vault = Vault::plain(&[&profile]).unwrap();
+        let vault = Vault::parse(&vault.to_json().unwrap()).unwrap();
+        assert!(!vault.is_encrypted());
+
+        let database = vault.database(None).unwrap();
+        assert_eq!(database.get_entries()[0].to_profile(), Ok(profile));
+    }
+
+    #[test]
+    fn encrypt_unsupported_profile() {
+        let mut profile = Profile::new("alice", "JBSWY3DPEHPK3PXP");
+        profile.set_t0(10);
+
+        assert!(Vault::encrypt_with(&[&profile], "secret", 10).is_err());
+        assert_eq!(
+            Vault::validate_profile(&profile),
+            Err(ValidationError::Unsupported("T0 must be 0."))
+        );
+    }
+
+    #[test]
+    fn generate_uuid_v4() {
+        let uuid = generate_uuid();
+
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(uuid.split('-').map(|part| part.len()).eq([8, 4, 4, 4, 12]));
+    }
+
+    #[test]
+    fn parse_not_aegis_export() {
+        assert!(Vault::parse("{\"entries\": []}").is_err());
+        assert!(Vault::parse("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP").is_err());
+    }
+}
@@ -0,0 +1,564 @@
+extern crate serde_json;
+
+use super::age_vault;
+use super::config::ProfileType;
+use super::filter::Filter;
+use super::mfa::{self, Mfa};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// エージェントのソケットを指定する環境変数
+pub const SOCKET_ENV: &str = "MFA_CLI_AGENT_SOCK";
+// ソケットのファイル名
+const SOCKET_FILE_NAME: &str = "agent.sock";
+// ソケットを置く一時ディレクトリの prefix
+const SOCKET_DIR_PREFIX: &str = "mfa-cli-";
+// 接続を待つ間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// 1 つの接続でリクエストを待つ時間
+const IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A request to the agent. One JSON per line.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    // Get the code of a profile. The counter of a HOTP profile is used up.
+    // HOTP profiles can't be watched.
+    Code {
+        profile: String,
+        #[serde(default)]
+        watch: bool,
+        // The profile must be chosen by the filter.
+        #[serde(default)]
+        filter: Filter,
+        // Don't resolve the profile by a prefix or fuzzy matching.
+        #[serde(default)]
+        exact: bool,
+    },
+    // Forget the profiles.
+    Lock,
+    // Read the profile file. The passphrase is required if it is encrypted with a passphrase.
+    // The identity passphrase is required if it is encrypted to age recipients
+    // and the identity is an encrypted SSH key. The agent never asks them.
+    Unlock {
+        passphrase: Option<String>,
+        #[serde(default)]
+        identity_passphrase: Option<String>,
+    },
+    // Stop the agent.
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Code { code: String },
+    Ok,
+    // The agent doesn't have the profiles.
+    Locked,
+    NeedPassphrase,
+    NeedIdentityPassphrase,
+    Error { message: String },
+}
+
+// プロファイルのファイルを開く関数。テストでは差し替える
+// 引数はパスフレーズと SSH の鍵のパスフレーズ
+type Open = Box<dyn Fn(Option<&str>, Option<&str>) -> Result<Mfa, String>>;
+
+// The agent which keeps the unlocked profiles in memory
+//
+// The profiles are forgotten after the idle timeout,
+// or when the profile file is changed by another process.
+pub struct Agent {
+    mfa: Option<Mfa>,
+    modified: Option<SystemTime>,
+    last_used: Instant,
+    timeout: Option<Duration>,
+    open: Open,
+}
+
+impl Agent {
+    // Build a locked agent. The timeout is disabled with None.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self::with_open(timeout, Box::new(Mfa::with_passphrase))
+    }
+
+    fn with_open(timeout: Option<Duration>, open: Open) -> Self {
+        Self {
+            mfa: None,
+            modified: None,
+            last_used: Instant::now(),
+            timeout,
+            open,
+        }
+    }
+
+    pub fn handle(&mut self, request: Request) -> Response {
+        self.expire();
+        self.last_used = Instant::now();
+
+        match request {
+            Request::Code {
+                profile,
+                watch,
+                filter,
+                exact,
+            } => {
+                let mfa = match &mut self.mfa {
+                    Some(mfa) => mfa,
+                    None => return Response::Locked,
+                };
+                let profile = match mfa.resolve_profile_name(&profile, &filter, exact) {
+                    Ok(profile) => profile,
+                    Err(message) => return Response::Error { message },
+                };
+                let profile_type = mfa.get_profile_by_name(&profile).map(|p| p.get_type());
+                if watch && profile_type == Some(ProfileType::Hotp) {
+                    return Response::Error {
+                        message: format!("can't watch a HOTP profile: {}", profile),
+                    };
+                }
+                let response = match mfa.consume_code_by_name(&profile) {
+                    Ok(code) => Response::Code { code },
+                    Err(message) => Response::Error { message },
+                };
+                // 他のコマンドが書き換えていれば書き込まずに忘れる
+                // 書き込めなかったコードは呼び出し側がファイルから読み直す
+                if mfa.is_changed() {
+                    self.lock();
+                    if let Response::Error { .. } = response {
+                        return Response::Locked;
+                    }
+                    return response;
+                }
+                // HOTP のカウンターを書き込んだので更新日時を取り直す
+                self.modified = mfa.modified();
+                response
+            }
+            Request::Lock => {
+                self.lock();
+                Response::Ok
+            }
+            Request::Unlock {
+                passphrase,
+                identity_passphrase,
+            } => match (self.open)(passphrase.as_deref(), identity_passphrase.as_deref()) {
+                Ok(mut mfa) => {
+                    // 保持している間は他のコマンドをブロックしない
+                    mfa.release_lock();
+                    self.modified = mfa.modified();
+                    self.mfa = Some(mfa);
+                    Response::Ok
+                }
+                Err(message) if message == mfa::PASSPHRASE_REQUIRED => Response::NeedPassphrase,
+                Err(message) if message == age_vault::IDENTITY_PASSPHRASE_REQUIRED => {
+                    Response::NeedIdentityPassphrase
+                }
+                Err(message) => Response::Error { message },
+            },
+            Request::Stop => {
+                self.lock();
+                Response::Ok
+            }
+        }
+    }
+
+    // Lock if it is idle for the timeout or the profile file is changed.
+    fn expire(&mut self) {
+        let mfa = match &self.mfa {
+            Some(mfa) => mfa,
+            None => return,
+        };
+
+        let idle = match self.timeout {
+            Some(timeout) => timeout <= self.last_used.elapsed(),
+            None => false,
+        };
+        if idle || mfa.modified() != self.modified {
+            self.lock();
+        }
+    }
+
+    fn lock(&mut self) {
+        self.mfa = None;
+        self.modified = None;
+    }
+
+    // Serve requests on the socket until a stop request.
+    //
+    // The socket is readable and writable only by the user.
+    pub fn serve(&mut self, socket: &Path) -> Result<(), String> {
+        let listener = match UnixListener::bind(socket) {
+            Ok(listener) => listener,
+            Err(err) => return Err(format!("failed to bind {}: {}", socket.display(), err)),
+        };
+        if let Err(err) = fs::set_permissions(socket, fs::Permissions::from_mode(0o600)) {
+            return Err(err.to_string());
+        }
+        if let Err(err) = listener.set_nonblocking(true) {
+            return Err(err.to_string());
+        }
+
+        let result = loop {
+            match listener.accept() {
+                Ok((stream, _)) => match self.serve_stream(stream) {
+                    Ok(true) => break Ok(()),
+                    // 壊れたリクエストは無視して次を待つ
+                    Ok(false) | Err(_) => {}
+                },
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.expire();
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(err) => break Err(err.to_string()),
+            }
+        };
+
+        let _ = fs::remove_file(socket);
+        result
+    }
+
+    // Handle a request. It returns true if the agent should stop.
+    fn serve_stream(&mut self, stream: UnixStream) -> io::Result<bool> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let (response, stop) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let stop = request == Request::Stop;
+                (self.handle(request), stop)
+            }
+            Err(err) => (
+                Response::Error {
+                    message: format!("broken request: {}", err),
+                },
+                false,
+            ),
+        };
+
+        write_line(&stream, &response)?;
+        Ok(stop)
+    }
+}
+
+// Send a request to the agent.
+pub fn request(socket: &Path, request: &Request) -> Result<Response, String> {
+    let stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(err) => return Err(format!("can't connect to the agent: {}", err)),
+    };
+    let result = stream
+        .set_read_timeout(Some(IO_TIMEOUT))
+        .and_then(|_| write_line(&stream, request))
+        .and_then(|_| {
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line)?;
+            Ok(line)
+        });
+
+    match result {
+        Ok(line) => match serde_json::from_str(&line) {
+            Ok(response) => Ok(response),
+            Err(err) => Err(format!("broken response from the agent: {}", err)),
+        },
+        Err(err) => Err(format!("failed to talk to the agent: {}", err)),
+    }
+}
+
+fn write_line<T: Serialize>(mut stream: &UnixStream, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+// The socket of the agent in $MFA_CLI_AGENT_SOCK
+pub fn socket_from_env() -> Option<PathBuf> {
+    match env::var_os(SOCKET_ENV) {
+        Some(socket) if !socket.is_empty() => Some(PathBuf::from(socket)),
+        _ => None,
+    }
+}
+
+// Create a directory only for the user in the temporary directory and return the socket path in it.
+pub fn new_socket_path() -> Result<PathBuf, String> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_nanos());
+    let dir = env::temp_dir().join(format!(
+        "{}{}-{:08x}",
+        SOCKET_DIR_PREFIX,
+        process::id(),
+        nanos
+    ));
+
+    // 既に存在する場合は失敗させて他人のディレクトリを使わない
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(_) => Ok(dir.join(SOCKET_FILE_NAME)),
+        Err(err) => Err(format!("failed to create {}: {}", dir.display(), err)),
+    }
+}
+
+// Remove the directory made by new_socket_path after the agent stops.
+//
+// Other directories are left as they are.
+pub fn remove_socket_dir(socket: &Path) {
+    let dir = match socket.parent() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let made = dir.parent() == Some(env::temp_dir().as_path())
+        && dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map_or(false, |name| name.starts_with(SOCKET_DIR_PREFIX));
+    if made {
+        // 空でなければ失敗するので他のファイルは消さない
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    // プロファイルのファイルは dir に置く
+    fn open(dir: &Path) -> Open {
+        let dir = dir.to_path_buf();
+        Box::new(move |passphrase, _| match passphrase {
+            Some("passphrase") => {
+                let mut mfa = Mfa::in_dir(&dir, None, None)?;
+                if mfa.get_profile_by_name("test").is_none() {
+                    mfa.add_profile(config::Profile::new("test", "JBSWY3DPEHPK3PXP"))?;
+                }
+                Ok(mfa)
+            }
+            Some(_) => Err(String::from("The passphrase is incorrect.")),
+            None => Err(String::from(mfa::PASSPHRASE_REQUIRED)),
+        })
+    }
+
+    fn code_request() -> Request {
+        Request::Code {
+            profile: String::from("test"),
+            watch: false,
+            filter: Default::default(),
+            exact: false,
+        }
+    }
+
+    #[test]
+    fn unlock_and_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut agent = Agent::with_open(None, open(dir.path()));
+
+        assert_eq!(agent.handle(code_request()), Response::Locked);
+        assert_eq!(
+            agent.handle(Request::Unlock {
+                passphrase: None,
+                identity_passphrase: None,
+            }),
+            Response::NeedPassphrase
+        );
+        assert_eq!(
+            agent.handle(Request::Unlock {
+                passphrase: Some(String::from("wrong")),
+                identity_passphrase: None,
+            }),
+            Response::Error {
+                message: String::from("The passphrase is incorrect.")
+            }
+        );
+        assert_eq!(
+            agent.handle(Request::Unlock {
+                passphrase: Some(String::from("passphrase")),
+                identity_passphrase: None,
+            }),
+            Response::Ok
+        );
+        assert!(matches!(
+            agent.handle(code_request()),
+            Response::Code { .. }
+        ));
+        assert!(matches!(
+            agent.handle(Request::Code {
+                profile: String::from("unknown"),
+                watch: false,
+                filter: Default::default(),
+                exact: false,
+            }),
+            Response::Error { .. }
+        ));
+        assert!(matches!(
+            agent.handle(Request::Code {
+                profile: String::from("test"),
+                watch: false,
+                filter: Filter::new(&[String::from("work")], None, None),
+                exact: false,
+            }),
+            Response::Error { .. }
+        ));
+
+        assert_eq!(agent.handle(Request::Lock), Response::Ok);
+        assert_eq!(agent.handle(code_request()), Response::Locked);
+    }
+
+    #[test]
+    fn lock_after_idle_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut agent = Agent::with_open(Some(Duration::from_millis(10)), open(dir.path()));
+        agent.handle(Request::Unlock {
+            passphrase: Some(String::from("passphrase")),
+            identity_passphrase: None,
+        });
+
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(agent.handle(code_request()), Response::Locked);
+    }
+
+    #[test]
+    fn lock_when_profile_file_is_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path().to_path_buf();
+        let open_home = move |_: Option<&str>, _: Option<&str>| -> Result<Mfa, String> {
+            let mut mfa = Mfa::in_dir(&home, None, None)?;
+            let mut profile = config::Profile::new("test", "JBSWY3DPEHPK3PXP");
+            profile.set_type(ProfileType::Hotp);
+            if mfa.get_profile_by_name("test").is_none() {
+                mfa.add_profile(profile)?;
+                mfa.dump()?;
+            }
+            Ok(mfa)
+        };
+        let mut agent = Agent::with_open(None, Box::new(open_home));
+        assert_eq!(
+            agent.handle(Request::Unlock {
+                passphrase: None,
+                identity_passphrase: None,
+            }),
+            Response::Ok
+        );
+
+        // 他のコマンドがプロファイルを足す
+        let mut other = Mfa::in_dir(dir.path(), None, None).unwrap();
+        other.register_profile("other", "JBSWY3DPEHPK3PXP").unwrap();
+        other.dump().unwrap();
+        drop(other);
+        // 更新日時が変わらなくても内容の違いで気付く
+        agent.modified = agent.mfa.as_ref().unwrap().modified();
+
+        assert_eq!(agent.handle(code_request()), Response::Locked);
+        let restored = Mfa::in_dir(dir.path(), None, None).unwrap();
+        assert!(restored.get_profile_by_name("other").is_some());
+        assert_eq!(restored.get_counter("test"), Ok(0));
+    }
+
+    #[test]
+    fn unlock_age_vault_with_encrypted_ssh_key() {
+        // ssh-keygen -t ed25519 -a 1 -N secretpw で作った鍵
+        let identity = Path::new("tests/fixtures/ssh-ed25519-encrypted");
+        let recipient = include_str!("../tests/fixtures/ssh-ed25519-encrypted.pub");
+        let dir = tempfile::tempdir().unwrap();
+        let mut mfa = Mfa::in_dir(dir.path(), None, None).unwrap();
+        mfa.register_profile("test", "JBSWY3DPEHPK3PXP").unwrap();
+        mfa.encrypt_to_recipients(&[recipient.to_string()]).unwrap();
+        mfa.dump().unwrap();
+        drop(mfa);
+
+        let home = dir.path().to_path_buf();
+        let mut agent = Agent::with_open(
+            None,
+            Box::new(move |_, identity_passphrase| {
+                Mfa::in_dir(&home, Some(identity), identity_passphrase)
+            }),
+        );
+        let unlock = |identity_passphrase: Option<&str>| Request::Unlock {
+            passphrase: None,
+            identity_passphrase: identity_passphrase.map(String::from),
+        };
+
+        assert_eq!(agent.handle(unlock(None)), Response::NeedIdentityPassphrase);
+        assert_eq!(
+            agent.handle(unlock(Some("wrong"))),
+            Response::Error {
+                message: String::from("The passphrase of the identity is incorrect.")
+            }
+        );
+        assert_eq!(agent.handle(unlock(Some("secretpw"))), Response::Ok);
+        assert!(matches!(
+            agent.handle(code_request()),
+            Response::Code { .. }
+        ));
+    }
+
+    #[test]
+    fn unlock_request_without_identity_passphrase() {
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"type":"unlock","passphrase":null}"#).unwrap(),
+            Request::Unlock {
+                passphrase: None,
+                identity_passphrase: None,
+            }
+        );
+    }
+
+    #[test]
+    fn serve_on_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join(SOCKET_FILE_NAME);
+
+        let server_socket = socket.clone();
+        let home = dir.path().to_path_buf();
+        let server = thread::spawn(move || {
+            Agent::with_open(None, open(&home))
+                .serve(&server_socket)
+                .unwrap()
+        });
+        while !socket.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            request(
+                &socket,
+                &Request::Unlock {
+                    passphrase: Some(String::from("passphrase")),
+                    identity_passphrase: None,
+                }
+            ),
+            Ok(Response::Ok)
+        );
+        assert!(matches!(
+            request(&socket, &code_request()),
+            Ok(Response::Code { .. })
+        ));
+        assert_eq!(request(&socket, &Request::Stop), Ok(Response::Ok));
+
+        server.join().unwrap();
+        assert!(!socket.exists());
+    }
+
+    #[test]
+    fn socket_path_is_private() {
+        let socket = new_socket_path().unwrap();
+        let dir = socket.parent().unwrap();
+
+        let mode = fs::metadata(dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        remove_socket_dir(&socket);
+        assert!(!dir.exists());
+    }
+}
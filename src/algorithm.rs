@@ -0,0 +1,78 @@
+use super::hmac_sha1;
+use super::hmac_sha2;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+// HMAC のハッシュアルゴリズム
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    // Calculate HMAC of the input with the key.
+    pub fn hmac(&self, key: &[u8], input: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Sha1 => hmac_sha1::gen_hmac_sha1(key, input).map(|hash| hash.to_vec()),
+            Self::Sha256 => hmac_sha2::gen_hmac_sha256(key, input).map(|hash| hash.to_vec()),
+            Self::Sha512 => hmac_sha2::gen_hmac_sha512(key, input).map(|hash| hash.to_vec()),
+        }
+    }
+
+    // It returns true if it is the default algorithm (SHA1).
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha1 => write!(f, "SHA1"),
+            Self::Sha256 => write!(f, "SHA256"),
+            Self::Sha512 => write!(f, "SHA512"),
+        }
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+
+    // Accepts "SHA1", "SHA256" and "SHA512" case-insensitively, with or without a hyphen.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().replace('-', "").as_str() {
+            "SHA1" => Ok(Self::Sha1),
+            "SHA256" => Ok(Self::Sha256),
+            "SHA512" => Ok(Self::Sha512),
+            _ => Err(format!("Unsupported algorithm: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_algorithm() {
+        assert_eq!("sha1".parse(), Ok(Algorithm::Sha1));
+        assert_eq!("SHA256".parse(), Ok(Algorithm::Sha256));
+        assert_eq!("sha-512".parse(), Ok(Algorithm::Sha512));
+    }
+
+    #[test]
+    fn parse_unsupported_algorithm() {
+        assert!("md5".parse::<Algorithm>().is_err());
+    }
+
+    #[test]
+    fn display_algorithm() {
+        assert_eq!(Algorithm::Sha256.to_string(), "SHA256");
+    }
+}
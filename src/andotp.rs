@@ -0,0 +1,255 @@
+extern crate serde_json;
+
+use super::algorithm::Algorithm;
+use super::config::{Profile, ProfileType, ValidationError};
+use super::crypto;
+use super::import;
+use super::otpauth;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// 暗号化されたバックアップの先頭にある PBKDF2 の反復回数の長さ
+const ITERATIONS_LENGTH: usize = 4;
+// PBKDF2 の salt の長さ
+const SALT_LENGTH: usize = 12;
+// 受け付ける PBKDF2 の反復回数。andOTP は 140000~160000 を使う
+// 範囲外の値で鍵を導出すると別のファイルを渡されたときに終わらなくなる
+const ITERATIONS_RANGE: std::ops::RangeInclusive<u32> = 1_000..=1_000_000;
+
+// andOTP のバックアップのエントリー
+//
+// A plain backup is a JSON array of entries.
+// An encrypted backup (.json.aes) is the JSON encrypted with AES-256-GCM.
+#[derive(Deserialize, Debug)]
+pub struct Entry {
+    #[serde(rename = "type")]
+    entry_type: String,
+    #[serde(default)]
+    secret: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default)]
+    algorithm: String,
+    digits: Option<u8>,
+    period: Option<u64>,
+    counter: Option<u64>,
+}
+
+// Whether the backup is encrypted.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(data).is_err()
+}
+
+// Parse an andOTP backup.
+//
+// The password is required only when the backup is encrypted.
+pub fn parse(data: &[u8], password: Option<&str>) -> Result<Vec<Entry>, String> {
+    if !is_encrypted(data) {
+        return parse_json(data);
+    }
+
+    let password = match password {
+        Some(password) => password,
+        None => return Err(String::from("The password is required.")),
+    };
+    parse_json(&decrypt(data, password)?)
+}
+
+fn parse_json(data: &[u8]) -> Result<Vec<Entry>, String> {
+    match serde_json::from_slice(data) {
+        Ok(entries) => Ok(entries),
+        Err(_) => Err(String::from("It is not an andOTP backup.")),
+    }
+}
+
+// Decrypt an encrypted backup.
+//
+// The current format is [iterations (4 bytes)][salt (12 bytes)][nonce (12 bytes)][ciphertext]
+// with a key derived by PBKDF2-HMAC-SHA1.
+// The old format is [nonce (12 bytes)][ciphertext] with SHA-256 of the password as a key.
+fn decrypt(data: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    let header_length = ITERATIONS_LENGTH + SALT_LENGTH + crypto::NONCE_LENGTH;
+    if data.len() > header_length + crypto::TAG_LENGTH {
+        let mut iterations = [0u8; ITERATIONS_LENGTH];
+        iterations.copy_from_slice(&data[..ITERATIONS_LENGTH]);
+        let salt = &data[ITERATIONS_LENGTH..ITERATIONS_LENGTH + SALT_LENGTH];
+        let nonce = &data[ITERATIONS_LENGTH + SALT_LENGTH..header_length];
+
+        let iterations = u32::from_be_bytes(iterations);
+        if ITERATIONS_RANGE.contains(&iterations) {
+            let key = crypto::pbkdf2_sha1(password, salt, iterations);
+            if let Ok(plaintext) = crypto::aes_gcm_decrypt(&key, nonce, &data[header_length..]) {
+                return Ok(plaintext);
+            }
+        }
+    }
+
+    if data.len() > crypto::NONCE_LENGTH + crypto::TAG_LENGTH {
+        let key = Sha256::digest(password.as_bytes());
+        let (nonce, ciphertext) = data.split_at(crypto::NONCE_LENGTH);
+        if let Ok(plaintext) = crypto::aes_gcm_decrypt(&key, nonce, ciphertext) {
+            return Ok(plaintext);
+        }
+    }
+
+    Err(String::from(
+        "The password is incorrect or the backup is broken.",
+    ))
+}
+
+impl import::Entry for Entry {
+    // A label to show the entry without the secret. "Issuer:Label" or "Label"
+    fn label(&self) -> String {
+        if self.issuer.is_empty() || self.label.starts_with(&format!("{}:", self.issuer)) {
+            return self.label.clone();
+        }
+
+        format!("{}:{}", self.issuer, self.label)
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the issuer and the label like otpauth URI.
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        let profile_type = match self.entry_type.parse::<ProfileType>() {
+            Ok(profile_type) => profile_type,
+            Err(_) => {
+                return Err(ValidationError::Unsupported(
+                    "Type must be TOTP, HOTP or STEAM.",
+                ))
+            }
+        };
+        let algorithm = match self.algorithm.parse::<Algorithm>() {
+            Ok(algorithm) => algorithm,
+            Err(_) => {
+                return Err(ValidationError::Unsupported(
+                    "Algorithm must be SHA1, SHA256 or SHA512.",
+                ))
+            }
+        };
+        if self.secret.is_empty() {
+            return Err(ValidationError::Requires("Secret must be present."));
+        }
+
+        let account = match self.label.split_once(':') {
+            Some((_, account)) => account.trim(),
+            None => self.label.trim(),
+        };
+        let name = import::profile_name(&self.issuer, account);
+        let mut profile = Profile::new(&name, &otpauth::normalize_secret(&self.secret)?);
+        profile.set_issuer(&self.issuer);
+        profile.set_account(account);
+        profile.set_type(profile_type);
+        profile.set_algorithm(algorithm);
+        import::set_digits(&mut profile, self.digits);
+        if let Some(period) = self.period {
+            profile.set_period(period);
+        }
+        if let Some(counter) = self.counter {
+            profile.set_counter(counter);
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    const PLAIN: &[u8] = include_bytes!("../tests/fixtures/andotp-plain.json");
+    // encrypted with the password "test"
+    const ENCRYPTED: &[u8] = include_bytes!("../tests/fixtures/andotp-encrypted.json.aes");
+
+    #[test]
+    fn parse_plain_backup() {
+        assert!(!is_encrypted(PLAIN));
+
+        let profiles: Vec<Result<Profile, ValidationError>> = parse(PLAIN, None)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.to_profile())
+            .collect();
+
+        let mut totp = Profile::new("Example-alice@exampl", "JBSWY3DPEHPK3PXP");
+        totp.set_issuer("Example");
+        totp.set_account("alice@example.com");
+        totp.set_algorithm(Algorithm::Sha256);
+        totp.set_digits(8);
+        totp.set_period(60);
+        let mut hotp = Profile::new("bob", "GEZDGNBVGY3TQOJQ");
+        hotp.set_account("bob");
+        hotp.set_type(ProfileType::Hotp);
+        hotp.set_counter(5);
+        let mut steam = Profile::new("Steam-carol", "JBSWY3DPEHPK3PXP");
+        steam.set_issuer("Steam");
+        steam.set_account("carol");
+        steam.set_type(ProfileType::Steam);
+
+        assert_eq!(
+            profiles,
+            vec![
+                Ok(totp),
+                Ok(hotp),
+                Ok(steam),
+                Err(ValidationError::Unsupported(
+                    "Type must be TOTP, HOTP or STEAM."
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_encrypted_backup() {
+        assert!(is_encrypted(ENCRYPTED));
+
+        let labels: Vec<String> = parse(ENCRYPTED, Some("test"))
+            .unwrap()
+            .iter()
+            .map(|entry| entry.label())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Example:alice@example.com",
+                "bob",
+                "Steam:carol",
+                "Example:dave"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_old_encrypted_backup() {
+        let key = Sha256::digest(b"test");
+        let nonce = [0u8; crypto::NONCE_LENGTH];
+        let mut data = nonce.to_vec();
+        data.extend(crypto::aes_gcm_encrypt(&key, &nonce, PLAIN).unwrap());
+
+        assert_eq!(parse(&data, Some("test")).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn parse_with_too_many_iterations() {
+        // "otpa" は約 19 億回として読まれる
+        let mut data = b"otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP".to_vec();
+        data.extend([0u8; crypto::TAG_LENGTH]);
+        assert!(is_encrypted(&data));
+
+        assert_eq!(
+            parse(&data, Some("test")).unwrap_err(),
+            "The password is incorrect or the backup is broken."
+        );
+    }
+
+    #[test]
+    fn parse_with_wrong_password() {
+        assert!(parse(ENCRYPTED, Some("wrong")).is_err());
+        assert_eq!(
+            parse(ENCRYPTED, None).unwrap_err(),
+            "The password is required."
+        );
+    }
+}
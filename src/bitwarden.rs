@@ -0,0 +1,181 @@
+extern crate serde_json;
+
+use super::config::{Profile, ProfileType, ValidationError};
+use super::import;
+use super::otpauth::{self, KeyUri};
+use serde::Deserialize;
+
+const OTPAUTH_SCHEME: &str = "otpauth://";
+// Bitwarden は Steam Guard の secret を steam://SECRET で保存する
+const STEAM_SCHEME: &str = "steam://";
+
+// Bitwarden の "Export vault" で出力される JSON (.json, not encrypted)
+#[derive(Deserialize, Debug)]
+pub struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Item {
+    #[serde(default)]
+    name: String,
+    login: Option<Login>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Login {
+    username: Option<String>,
+    totp: Option<String>,
+}
+
+impl Export {
+    // Parse a Bitwarden export.
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let export: Self = match serde_json::from_str(data) {
+            Ok(export) => export,
+            Err(_) => return Err(String::from("It is not a Bitwarden JSON export.")),
+        };
+        if export.encrypted {
+            return Err(String::from(
+                "Encrypted exports are not supported. Export the vault in JSON (not encrypted).",
+            ));
+        }
+
+        Ok(export)
+    }
+
+    // Items which have a TOTP seed
+    pub fn into_items(self) -> Vec<Item> {
+        self.items
+            .into_iter()
+            .filter(|item| !item.totp().is_empty())
+            .collect()
+    }
+}
+
+impl Item {
+    fn totp(&self) -> &str {
+        match &self.login {
+            Some(Login {
+                totp: Some(totp), ..
+            }) => totp.trim(),
+            _ => "",
+        }
+    }
+
+    fn username(&self) -> &str {
+        match &self.login {
+            Some(Login {
+                username: Some(username),
+                ..
+            }) => username.trim(),
+            _ => "",
+        }
+    }
+}
+
+impl import::Entry for Item {
+    // A label to show the item without the secret. "Name:Username" or "Name"
+    fn label(&self) -> String {
+        if self.username().is_empty() {
+            return self.name.clone();
+        }
+
+        format!("{}:{}", self.name, self.username())
+    }
+
+    // Build a profile.
+    //
+    // login.totp is a bare base32 seed, an otpauth URI or steam://SEED.
+    // The name is derived from the item name and the username.
+    // They are also the issuer and the account unless the URI has them.
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        let totp = self.totp();
+        let mut profile = if import::has_scheme(totp, OTPAUTH_SCHEME) {
+            KeyUri::parse(totp)?.to_profile()
+        } else if import::has_scheme(totp, STEAM_SCHEME) {
+            let secret = otpauth::normalize_secret(&totp[STEAM_SCHEME.len()..])?;
+            let mut profile = Profile::new("", &secret);
+            profile.set_type(ProfileType::Steam);
+            profile
+        } else {
+            Profile::new("", &otpauth::normalize_secret(totp)?)
+        };
+
+        profile.set_name(&import::profile_name(&self.name, self.username()));
+        if profile.get_issuer().is_none() {
+            profile.set_issuer(&self.name);
+        }
+        if profile.get_account().is_none() {
+            profile.set_account(self.username());
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::Algorithm;
+    use crate::import::Entry as _;
+
+    const EXPORT: &str = include_str!("../tests/fixtures/bitwarden.json");
+
+    #[test]
+    fn parse_export() {
+        let items = Export::parse(EXPORT).unwrap().into_items();
+        let profiles: Vec<Result<Profile, ValidationError>> =
+            items.iter().map(|item| item.to_profile()).collect();
+
+        let mut seed = Profile::new("GitHub-alice", "JBSWY3DPEHPK3PXP");
+        seed.set_issuer("GitHub");
+        seed.set_account("alice");
+        let mut uri = Profile::new("Example-bob@example", "GEZDGNBVGY3TQOJQ");
+        uri.set_issuer("Example");
+        uri.set_account("bob");
+        uri.set_algorithm(Algorithm::Sha256);
+        uri.set_digits(8);
+        let mut steam = Profile::new("Steam-carol", "JBSWY3DPEHPK3PXP");
+        steam.set_issuer("Steam");
+        steam.set_account("carol");
+        steam.set_type(ProfileType::Steam);
+
+        assert_eq!(
+            profiles,
+            vec![
+                Ok(seed),
+                Ok(uri),
+                Ok(steam),
+                Err(ValidationError::InvalidFormat(
+                    "Secret must be encoded in base32."
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_invalid_seed() {
+        let export = r#"{"encrypted": false, "items": [
+            {"type": 1, "name": "Bad", "login": {"username": "eve", "totp": "not*base32!"}},
+            {"type": 1, "name": "Steam", "login": {"username": "eve", "totp": "steam://1"}}
+        ]}"#;
+        let items = Export::parse(export).unwrap().into_items();
+
+        for item in items {
+            assert_eq!(
+                item.to_profile(),
+                Err(ValidationError::InvalidFormat(
+                    "Secret must be encoded in base32."
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn parse_encrypted_export() {
+        assert!(Export::parse(r#"{"encrypted": true, "data": "..."}"#).is_err());
+    }
+}
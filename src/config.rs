@@ -3,10 +3,16 @@ extern crate regex;
 extern crate serde;
 extern crate toml;
 
+use super::algorithm::Algorithm;
+use super::datetime;
+use super::schema;
+use super::totp;
 use regex::Regex;
 use serde::Deserialize;
 use serde::Serialize;
 use std::fmt;
+use std::str::FromStr;
+use toml::value::Datetime;
 
 #[derive(Debug, PartialEq)]
 pub enum ValidationError {
@@ -15,10 +21,19 @@ pub enum ValidationError {
     TooLongLength(&'static str),    // The length of the value of a field is too long.
     Deplication(&'static str),      // The value of a field is already registered.
     Requires(&'static str),         // A field must have any value.
+    OutOfRange(&'static str),       // The value of a field is out of the allowed range.
+    InvalidFormat(&'static str),    // The value can't be parsed.
+    Unsupported(&'static str),      // The value is valid but not supported.
 }
 
 type ValidationResult = Result<(), ValidationError>;
 
+// 名前の長さの下限と上限
+const NAME_MIN_LENGTH: usize = 3;
+const NAME_MAX_LENGTH: usize = 20;
+// タグの長さの上限
+const TAG_MAX_LENGTH: usize = 30;
+
 impl fmt::Display for ValidationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -26,23 +41,43 @@ impl fmt::Display for ValidationError {
             | Self::TooShortLength(msg)
             | Self::TooLongLength(msg)
             | Self::Deplication(msg)
-            | Self::Requires(msg) => write!(f, "{}", msg),
+            | Self::Requires(msg)
+            | Self::OutOfRange(msg)
+            | Self::InvalidFormat(msg)
+            | Self::Unsupported(msg) => write!(f, "{}", msg),
         }
     }
 }
 
 // 設定
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
+    // The version of the schema. Old files are upgraded by schema::migrate.
+    #[serde(default)]
+    version: u32,
     profiles: Vec<Profile>,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: schema::VERSION,
+            profiles: Vec::new(),
+        }
+    }
+}
+
 impl Config {
     pub fn new_profile(&mut self, name: &str, secret: &str) -> ValidationResult {
         self.push_profile(Profile::new(name, secret))
     }
 
-    fn push_profile(&mut self, profile: Profile) -> ValidationResult {
+    // Register a profile. The creation time is recorded if the profile doesn't have it.
+    pub fn push_profile(&mut self, mut profile: Profile) -> ValidationResult {
+        if profile.created_at.is_none() {
+            profile.created_at = Some(datetime::now());
+        }
+
         match self.validate_profile(&profile) {
             Ok(_) => {
                 // TODO: test name duplication
@@ -61,6 +96,60 @@ impl Config {
         profile.is_vaild()
     }
 
+    // Replace a registered profile with an edited one.
+    //
+    // The edited profile is validated like a new one except for the name of itself,
+    // so it can also be renamed to a name which is not registered yet.
+    pub fn replace_profile(&mut self, name: &str, profile: Profile) -> Result<(), String> {
+        let index = match self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+        {
+            Some(index) => index,
+            None => return Err(format!("Can't find this profile: {}", name)),
+        };
+
+        let original = self.profiles.remove(index);
+        let (result, profile) = match self.validate_profile(&profile) {
+            Ok(_) => (Ok(()), profile),
+            Err(err) => (Err(err.to_string()), original),
+        };
+        // 順番は変えない
+        self.profiles.insert(index, profile);
+        result
+    }
+
+    // Rename a profile. Everything else of the profile is kept.
+    pub fn rename_profile(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        let mut profile = match self.find_by_name(name) {
+            Some(profile) => profile.clone(),
+            None => return Err(format!("Can't find this profile: {}", name)),
+        };
+
+        profile.set_name(new_name);
+        self.replace_profile(name, profile)
+    }
+
+    // Get a name which is not registered yet.
+    //
+    // If the name is already registered, a number suffix is appended. e.g. "name-2"
+    pub fn unique_name(&self, name: &str) -> String {
+        if self.find_by_name(name).is_none() {
+            return name.to_string();
+        }
+
+        (2..)
+            .map(|n| {
+                let suffix = format!("-{}", n);
+                let mut base = name.to_string();
+                base.truncate(NAME_MAX_LENGTH - suffix.len());
+                format!("{}{}", base, suffix)
+            })
+            .find(|candidate| self.find_by_name(candidate).is_none())
+            .unwrap()
+    }
+
     // Get the decoded secret value with a profile name.
     pub fn get_secret_by_name(&self, name: &str) -> Option<Vec<u8>> {
         if let Some(profile) = self.find_by_name(name) {
@@ -93,12 +182,18 @@ impl Config {
         }
     }
 
-    fn find_by_name(&self, name: &str) -> Option<&Profile> {
+    pub fn find_by_name(&self, name: &str) -> Option<&Profile> {
         self.profiles
             .iter()
             .find(|&profile| *profile.get_name() == *name)
     }
 
+    pub fn find_by_name_mut(&mut self, name: &str) -> Option<&mut Profile> {
+        self.profiles
+            .iter_mut()
+            .find(|profile| *profile.get_name() == *name)
+    }
+
     // Serialize to strings
     pub fn serialize(&self) -> Result<String, String> {
         match toml::to_string(&self) {
@@ -108,8 +203,10 @@ impl Config {
     }
 
     // Deserialize config from strings
+    //
+    // An old config is upgraded to the current version.
     pub fn deserialize(&mut self, content: &str) -> Result<(), String> {
-        match toml::from_str(content) {
+        match toml::from_str(&schema::migrate(content)?) {
             Ok(config) => {
                 *self = config;
                 Ok(())
@@ -119,11 +216,129 @@ impl Config {
     }
 }
 
+// MFA コードの種類
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileType {
+    #[default]
+    Totp, // Time-based (RFC 6238)
+    Hotp,  // Counter-based (RFC 4226)
+    Steam, // Steam Guard (time-based with 5 characters)
+}
+
+impl ProfileType {
+    // It returns true if it is the default type (TOTP).
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for ProfileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Totp => write!(f, "totp"),
+            Self::Hotp => write!(f, "hotp"),
+            Self::Steam => write!(f, "steam"),
+        }
+    }
+}
+
+impl FromStr for ProfileType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "totp" => Ok(Self::Totp),
+            "hotp" => Ok(Self::Hotp),
+            "steam" => Ok(Self::Steam),
+            _ => Err(format!("Unsupported type: {}", s)),
+        }
+    }
+}
+
 // MFA の設定
-#[derive(Serialize, Deserialize, Default, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Profile {
     name: String,
     secret: String,
+    #[serde(
+        rename = "type",
+        default,
+        skip_serializing_if = "ProfileType::is_default"
+    )]
+    profile_type: ProfileType,
+    #[serde(default, skip_serializing_if = "Algorithm::is_default")]
+    algorithm: Algorithm,
+    #[serde(default = "default_digits", skip_serializing_if = "is_default_digits")]
+    digits: u8,
+    #[serde(default = "default_period", skip_serializing_if = "is_default_period")]
+    period: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    t0: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    counter: u64,
+    // The service which issued the secret. e.g. GitHub
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    issuer: Option<String>,
+    // The account in the service. e.g. alice@example.com
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    account: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<Datetime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_used_at: Option<Datetime>,
+}
+
+fn default_digits() -> u8 {
+    totp::TOTP_DIGITS
+}
+
+fn is_default_digits(digits: &u8) -> bool {
+    *digits == default_digits()
+}
+
+fn default_period() -> u64 {
+    totp::TIME_STEP
+}
+
+fn is_default_period(period: &u64) -> bool {
+    *period == default_period()
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    match value.trim() {
+        "" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: Default::default(),
+            secret: Default::default(),
+            profile_type: Default::default(),
+            algorithm: Default::default(),
+            digits: default_digits(),
+            period: default_period(),
+            t0: 0,
+            counter: 0,
+            issuer: None,
+            account: None,
+            notes: None,
+            tags: Vec::new(),
+            created_at: None,
+            last_used_at: None,
+        }
+    }
 }
 
 impl Profile {
@@ -131,6 +346,7 @@ impl Profile {
         Profile {
             name: name.to_string(),
             secret: secret.to_string(),
+            ..Default::default()
         }
     }
 
@@ -138,6 +354,150 @@ impl Profile {
         &self.name
     }
 
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    // Convert any label to a name which satisfies Self#is_valid_name.
+    //
+    // Illegal characters are replaced with a hyphen and the length is fit in 3~20 characters.
+    pub fn sanitize_name(label: &str) -> String {
+        let mut name = String::new();
+        for c in label.chars() {
+            let c = if c.is_ascii_alphanumeric() || "_@-".contains(c) {
+                c
+            } else {
+                '-'
+            };
+            if c == '-' && (name.is_empty() || name.ends_with('-')) {
+                continue;
+            }
+            name.push(c);
+        }
+        name.truncate(NAME_MAX_LENGTH);
+        let mut name = name.trim_end_matches('-').to_string();
+
+        while name.len() < NAME_MIN_LENGTH {
+            name.push('_');
+        }
+
+        name
+    }
+
+    pub fn get_type(&self) -> ProfileType {
+        self.profile_type
+    }
+
+    pub fn set_type(&mut self, profile_type: ProfileType) {
+        self.profile_type = profile_type;
+    }
+
+    pub fn get_algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn set_algorithm(&mut self, algorithm: Algorithm) {
+        self.algorithm = algorithm;
+    }
+
+    pub fn get_digits(&self) -> u8 {
+        self.digits
+    }
+
+    pub fn set_digits(&mut self, digits: u8) {
+        self.digits = digits;
+    }
+
+    pub fn get_period(&self) -> u64 {
+        self.period
+    }
+
+    pub fn set_period(&mut self, period: u64) {
+        self.period = period;
+    }
+
+    pub fn get_t0(&self) -> u64 {
+        self.t0
+    }
+
+    pub fn set_t0(&mut self, t0: u64) {
+        self.t0 = t0;
+    }
+
+    pub fn get_counter(&self) -> u64 {
+        self.counter
+    }
+
+    pub fn set_counter(&mut self, counter: u64) {
+        self.counter = counter;
+    }
+
+    pub fn get_issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    // Set the issuer. An empty issuer is removed.
+    pub fn set_issuer(&mut self, issuer: &str) {
+        self.issuer = non_empty(issuer);
+    }
+
+    pub fn get_account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    // Set the account. An empty account is removed.
+    pub fn set_account(&mut self, account: &str) {
+        self.account = non_empty(account);
+    }
+
+    pub fn get_notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    // Set the notes. Empty notes are removed.
+    pub fn set_notes(&mut self, notes: &str) {
+        self.notes = non_empty(notes);
+    }
+
+    pub fn get_tags(&self) -> &Vec<String> {
+        &self.tags
+    }
+
+    // Add a tag. It does nothing if the profile has the tag already.
+    pub fn add_tag(&mut self, tag: &str) {
+        let tag = tag.trim();
+        if !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    // Remove a tag. It returns false if the profile doesn't have the tag.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let len = self.tags.len();
+        self.tags.retain(|t| t != tag.trim());
+        self.tags.len() != len
+    }
+
+    pub fn get_created_at(&self) -> Option<&Datetime> {
+        self.created_at.as_ref()
+    }
+
+    pub fn set_created_at(&mut self, created_at: Datetime) {
+        self.created_at = Some(created_at);
+    }
+
+    pub fn get_last_used_at(&self) -> Option<&Datetime> {
+        self.last_used_at.as_ref()
+    }
+
+    pub fn set_last_used_at(&mut self, last_used_at: Datetime) {
+        self.last_used_at = Some(last_used_at);
+    }
+
+    pub fn set_secret(&mut self, secret: &str) {
+        self.secret = secret.to_string();
+    }
+
     // returns decoded secret
     pub fn get_secret(&self) -> Option<Vec<u8>> {
         base32::decode(base32::Alphabet::RFC4648 { padding: true }, &self.secret)
@@ -150,6 +510,12 @@ impl Profile {
 
         self.is_valid_secret()?;
 
+        self.is_valid_digits()?;
+
+        self.is_valid_period()?;
+
+        self.is_valid_tags()?;
+
         Ok(())
     }
 
@@ -159,12 +525,12 @@ impl Profile {
     //   - 3~20 characters
     //   - Alphabet or Number or Symbol (@-_)
     fn is_valid_name(&self) -> ValidationResult {
-        if self.name.len() < 3 {
+        if self.name.len() < NAME_MIN_LENGTH {
             return Err(ValidationError::TooShortLength(
                 "Name requires at least 3 characters.",
             ));
         }
-        if 20 < self.name.len() {
+        if NAME_MAX_LENGTH < self.name.len() {
             return Err(ValidationError::TooLongLength(
                 "Name requires 20 characters or less.",
             ));
@@ -193,6 +559,57 @@ impl Profile {
 
         Ok(())
     }
+
+    // Validate tags.
+    //
+    // Requires
+    //   - 1~30 characters
+    //   - Alphabet or Number or Symbol (-_./)
+    fn is_valid_tags(&self) -> ValidationResult {
+        const VALID_TAG_PATTERN: &str = r"^[[[:alnum:]]_./-]+\z";
+        let re = Regex::new(VALID_TAG_PATTERN).unwrap();
+
+        for tag in &self.tags {
+            if TAG_MAX_LENGTH < tag.len() {
+                return Err(ValidationError::TooLongLength(
+                    "Tag requires 30 characters or less.",
+                ));
+            }
+            if !re.is_match(tag) {
+                return Err(ValidationError::IllegalCharacter(
+                    "Tag can contain only alphabet, number and symbol (-_./) .",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Validate a digits field.
+    //
+    // Requires
+    //   - 6~9 digits
+    fn is_valid_digits(&self) -> ValidationResult {
+        if !(6..=9).contains(&self.digits) {
+            return Err(ValidationError::OutOfRange("Digits requires 6 to 9."));
+        }
+
+        Ok(())
+    }
+
+    // Validate a period field.
+    //
+    // Requires
+    //   - greater than 0 seconds
+    fn is_valid_period(&self) -> ValidationResult {
+        if self.period == 0 {
+            return Err(ValidationError::OutOfRange(
+                "Period requires at least 1 second.",
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -207,12 +624,24 @@ mod tests {
         assert_eq!(toml::to_string(&profile).unwrap(), expected);
     }
 
+    #[test]
+    fn serialize_profile_with_algorithm() {
+        let mut profile = Profile::new("test", "secret");
+        profile.set_algorithm(Algorithm::Sha256);
+        let expected = "name = \"test\"\nsecret = \"secret\"\nalgorithm = \"SHA256\"\n";
+
+        assert_eq!(toml::to_string(&profile).unwrap(), expected);
+    }
+
     #[test]
     fn serialize_config() {
         let config = Config {
             profiles: vec![Profile::new("test", "secret")],
+            ..Default::default()
         };
-        let expected = r#"[[profiles]]
+        let expected = r#"version = 2
+
+[[profiles]]
 name = "test"
 secret = "secret"
 "#;
@@ -230,6 +659,139 @@ secret = "secret"
         assert_eq!(config.profiles.len(), 1);
         assert_eq!(config.profiles[0].name, "test");
         assert_eq!(config.profiles[0].secret, "secret");
+        assert_eq!(config.profiles[0].algorithm, Algorithm::Sha1);
+        assert_eq!(config.profiles[0].digits, 6);
+        assert_eq!(config.profiles[0].period, 30);
+        assert_eq!(config.profiles[0].t0, 0);
+        assert_eq!(config.profiles[0].profile_type, ProfileType::Totp);
+    }
+
+    #[test]
+    fn serialize_profile_with_totp_parameters() {
+        let mut profile = Profile::new("test", "secret");
+        profile.set_digits(8);
+        profile.set_period(60);
+        profile.set_t0(100);
+        let expected = "name = \"test\"\nsecret = \"secret\"\ndigits = 8\nperiod = 60\nt0 = 100\n";
+
+        assert_eq!(toml::to_string(&profile).unwrap(), expected);
+    }
+
+    #[test]
+    fn serialize_hotp_profile() {
+        let mut profile = Profile::new("test", "secret");
+        profile.set_type(ProfileType::Hotp);
+        profile.set_counter(5);
+        let expected = "name = \"test\"\nsecret = \"secret\"\ntype = \"hotp\"\ncounter = 5\n";
+
+        assert_eq!(toml::to_string(&profile).unwrap(), expected);
+    }
+
+    #[test]
+    fn deserialize_config_with_hotp_profile() {
+        let string_config =
+            "[[profiles]]\nname = \"test\"\nsecret = \"secret\"\ntype = \"hotp\"\ncounter = 5\n";
+        let mut config: Config = Default::default();
+
+        config.deserialize(string_config).unwrap();
+
+        assert_eq!(config.profiles[0].profile_type, ProfileType::Hotp);
+        assert_eq!(config.profiles[0].counter, 5);
+    }
+
+    #[test]
+    fn deserialize_config_with_totp_parameters() {
+        let string_config =
+            "[[profiles]]\nname = \"test\"\nsecret = \"secret\"\ndigits = 8\nperiod = 60\nt0 = 100\n";
+        let mut config: Config = Default::default();
+
+        config.deserialize(string_config).unwrap();
+
+        assert_eq!(config.profiles[0].digits, 8);
+        assert_eq!(config.profiles[0].period, 60);
+        assert_eq!(config.profiles[0].t0, 100);
+    }
+
+    #[test]
+    fn serialize_profile_with_metadata() {
+        let mut profile = Profile::new("test", "secret");
+        profile.set_issuer("Example");
+        profile.set_account("alice");
+        profile.set_notes("backup codes are in the safe");
+        profile.add_tag("work");
+        profile.add_tag("work");
+        profile.set_created_at(datetime::from_unix(0));
+        let expected = r#"name = "test"
+secret = "secret"
+issuer = "Example"
+account = "alice"
+notes = "backup codes are in the safe"
+tags = ["work"]
+created_at = 1970-01-01T00:00:00Z
+"#;
+
+        assert_eq!(toml::to_string(&profile).unwrap(), expected);
+        assert_eq!(toml::from_str::<Profile>(expected).unwrap(), profile);
+    }
+
+    #[test]
+    fn deserialize_config_without_metadata() {
+        let string_config = "[[profiles]]\nname = \"test\"\nsecret = \"secret\"\n";
+        let mut config: Config = Default::default();
+
+        config.deserialize(string_config).unwrap();
+
+        assert_eq!(config.profiles[0].get_issuer(), None);
+        assert!(config.profiles[0].get_tags().is_empty());
+        assert_eq!(config.profiles[0].get_created_at(), None);
+    }
+
+    #[test]
+    fn push_profile_records_created_at() {
+        let mut config: Config = Default::default();
+        config.new_profile("test", "a").unwrap();
+
+        assert!(config.profiles[0].get_created_at().is_some());
+    }
+
+    #[test]
+    fn edit_metadata() {
+        let mut profile = Profile::new("test", "secret");
+        profile.set_issuer("Example");
+        profile.set_issuer("  ");
+        profile.add_tag("work");
+        profile.add_tag("home");
+
+        assert_eq!(profile.get_issuer(), None);
+        assert!(profile.remove_tag("work"));
+        assert!(!profile.remove_tag("work"));
+        assert_eq!(profile.get_tags(), &vec![String::from("home")]);
+    }
+
+    #[test]
+    fn validate_tags() {
+        let mut profile = Profile::new("test", "secret");
+        profile.add_tag("team/infra-1.2_x");
+        assert_eq!(profile.is_vaild(), Ok(()));
+
+        profile.add_tag("two words");
+        assert_eq!(
+            profile.is_vaild(),
+            Err(ValidationError::IllegalCharacter(
+                "Tag can contain only alphabet, number and symbol (-_./) ."
+            ))
+        );
+    }
+
+    #[test]
+    fn deserialize_config_with_algorithm() {
+        let string_config =
+            "[[profiles]]\nname = \"test\"\nsecret = \"secret\"\nalgorithm = \"SHA512\"\n";
+        let mut config: Config = Default::default();
+
+        config.deserialize(string_config).unwrap();
+
+        assert_eq!(config.profiles[0].algorithm, Algorithm::Sha512);
     }
 
     #[test]
@@ -311,4 +873,133 @@ secret = "secret"
             Err(ValidationError::Requires("Secret must be present."))
         );
     }
+
+    #[test]
+    fn push_profile_validation_when_digits_is_out_of_range() {
+        let mut config: Config = Default::default();
+        let mut profile = Profile::new("aaa", "secret");
+        profile.set_digits(10);
+        let result = config.push_profile(profile);
+
+        assert_eq!(
+            result,
+            Err(ValidationError::OutOfRange("Digits requires 6 to 9."))
+        );
+    }
+
+    #[test]
+    fn push_profile_validation_when_period_is_zero() {
+        let mut config: Config = Default::default();
+        let mut profile = Profile::new("aaa", "secret");
+        profile.set_period(0);
+        let result = config.push_profile(profile);
+
+        assert_eq!(
+            result,
+            Err(ValidationError::OutOfRange(
+                "Period requires at least 1 second."
+            ))
+        );
+    }
+
+    #[test]
+    fn sanitize_name_replaces_illegal_characters() {
+        assert_eq!(
+            Profile::sanitize_name("ACME Co/john.doe"),
+            "ACME-Co-john-doe"
+        );
+    }
+
+    #[test]
+    fn sanitize_name_fits_length() {
+        assert_eq!(Profile::sanitize_name("a"), "a__");
+        assert_eq!(Profile::sanitize_name(&"a".repeat(30)), "a".repeat(20));
+    }
+
+    #[test]
+    fn unique_name_when_name_is_not_registered() {
+        let config: Config = Default::default();
+
+        assert_eq!(config.unique_name("test"), "test");
+    }
+
+    #[test]
+    fn unique_name_when_name_is_registered() {
+        let mut config: Config = Default::default();
+        config.new_profile("test", "a").unwrap();
+        config.new_profile("test-2", "a").unwrap();
+        config.new_profile(&"a".repeat(20), "a").unwrap();
+
+        assert_eq!(config.unique_name("test"), "test-3");
+        assert_eq!(
+            config.unique_name(&"a".repeat(20)),
+            format!("{}-2", "a".repeat(18))
+        );
+    }
+
+    #[test]
+    fn replace_profile_keeps_the_order() {
+        let mut config: Config = Default::default();
+        config.new_profile("first", "a").unwrap();
+        config.new_profile("second", "a").unwrap();
+        let mut profile = config.find_by_name("first").unwrap().clone();
+        profile.set_secret("b");
+        profile.set_digits(8);
+
+        assert_eq!(config.replace_profile("first", profile), Ok(()));
+        assert_eq!(config.profiles[0].name, "first");
+        assert_eq!(config.profiles[0].secret, "b");
+        assert_eq!(config.profiles[0].digits, 8);
+        assert_eq!(config.profiles[1].name, "second");
+    }
+
+    #[test]
+    fn replace_profile_validation() {
+        let mut config: Config = Default::default();
+        config.new_profile("first", "a").unwrap();
+        let mut profile = config.find_by_name("first").unwrap().clone();
+        profile.set_digits(0);
+
+        assert!(config.replace_profile("first", profile.clone()).is_err());
+        assert_eq!(config.profiles[0].digits, 6);
+        assert_eq!(
+            config.replace_profile("missing", profile),
+            Err(String::from("Can't find this profile: missing"))
+        );
+    }
+
+    #[test]
+    fn rename_profile() {
+        let mut config: Config = Default::default();
+        config.new_profile("first", "a").unwrap();
+        config.find_by_name_mut("first").unwrap().add_tag("work");
+
+        assert_eq!(config.rename_profile("first", "renamed"), Ok(()));
+        assert!(config.find_by_name("first").is_none());
+        let profile = config.find_by_name("renamed").unwrap();
+        assert_eq!(profile.get_tags(), &vec![String::from("work")]);
+        assert!(profile.get_created_at().is_some());
+    }
+
+    #[test]
+    fn rename_profile_validation() {
+        let mut config: Config = Default::default();
+        config.new_profile("first", "a").unwrap();
+        config.new_profile("second", "a").unwrap();
+
+        assert_eq!(
+            config.rename_profile("first", "second"),
+            Err(String::from("This name already exists."))
+        );
+        assert_eq!(
+            config.rename_profile("first", "a b"),
+            Err(String::from(
+                "Name can contain only alphabet, number and symbol (@-_) ."
+            ))
+        );
+        // 同じ名前へのリネームは重複扱いしない
+        assert_eq!(config.rename_profile("first", "first"), Ok(()));
+        assert_eq!(config.profiles[0].name, "first");
+        assert_eq!(config.profiles[1].name, "second");
+    }
 }
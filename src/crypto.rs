@@ -0,0 +1,98 @@
+extern crate aes_gcm;
+extern crate pbkdf2;
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use sha1::Sha1;
+use sha2::Sha256;
+
+// AES-256-GCM の鍵の長さ
+pub const KEY_LENGTH: usize = 32;
+// AES-GCM の nonce の長さ
+pub const NONCE_LENGTH: usize = 12;
+// AES-GCM の tag の長さ
+pub const TAG_LENGTH: usize = 16;
+
+// AES-256-GCM で暗号化する
+//
+// The tag is appended to the ciphertext.
+pub fn aes_gcm_encrypt(key: &[u8], nonce: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = new_cipher(key, nonce)?;
+
+    match cipher.encrypt(Nonce::from_slice(nonce), plaintext) {
+        Ok(ciphertext) => Ok(ciphertext),
+        Err(_) => Err(String::from("Failed to encrypt.")),
+    }
+}
+
+// AES-256-GCM で復号する
+//
+// The ciphertext must be followed by the tag.
+pub fn aes_gcm_decrypt(key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = new_cipher(key, nonce)?;
+
+    match cipher.decrypt(Nonce::from_slice(nonce), ciphertext) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(_) => Err(String::from("Failed to decrypt.")),
+    }
+}
+
+// PBKDF2-HMAC-SHA1 で AES-256 の鍵を導出する
+pub fn pbkdf2_sha1(password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut key = vec![0u8; KEY_LENGTH];
+    pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+// PBKDF2-HMAC-SHA256 で AES-256 の鍵を導出する
+pub fn pbkdf2_sha256(password: &str, salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mut key = vec![0u8; KEY_LENGTH];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+pub fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; length];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn new_cipher(key: &[u8], nonce: &[u8]) -> Result<Aes256Gcm, String> {
+    if nonce.len() != NONCE_LENGTH {
+        return Err(String::from("The nonce is broken."));
+    }
+
+    match Aes256Gcm::new_from_slice(key) {
+        Ok(cipher) => Ok(cipher),
+        Err(_) => Err(String::from("The key is broken.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aes_gcm_round_trip() {
+        let key = random_bytes(KEY_LENGTH);
+        let nonce = random_bytes(NONCE_LENGTH);
+
+        let ciphertext = aes_gcm_encrypt(&key, &nonce, b"secret").unwrap();
+        assert_eq!(ciphertext.len(), 6 + TAG_LENGTH);
+        assert_eq!(
+            aes_gcm_decrypt(&key, &nonce, &ciphertext).unwrap(),
+            b"secret"
+        );
+        assert!(aes_gcm_decrypt(&random_bytes(KEY_LENGTH), &nonce, &ciphertext).is_err());
+    }
+
+    // from RFC 6070
+    #[test]
+    fn pbkdf2_sha1_rfc_6070() {
+        assert_eq!(
+            hex::encode(&pbkdf2_sha1("password", b"salt", 2)[..20]),
+            "ea6c014dc72d6f8ccd1ed92ace1d41f0d8de8957"
+        );
+    }
+}
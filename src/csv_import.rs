@@ -0,0 +1,281 @@
+extern crate csv;
+
+use super::config::{Profile, ValidationError};
+use super::import;
+use super::otpauth;
+use std::str::FromStr;
+
+// A column of CSV. The header name or the 1-based index.
+#[derive(Clone, Debug, PartialEq)]
+enum Column {
+    Header(String),
+    Index(usize),
+}
+
+// Columns of CSV which are mapped to profile fields.
+//
+// It is given as "field=column,...". e.g. "name=Username,secret=3,issuer=Title"
+// name and secret are read from "name" and "secret" columns if they are not given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Columns {
+    name: Column,
+    secret: Column,
+    issuer: Option<Column>,
+    digits: Option<Column>,
+    period: Option<Column>,
+}
+
+// A row of CSV
+#[derive(Debug)]
+pub struct Row {
+    line: u64,
+    name: String,
+    secret: String,
+    issuer: String,
+    digits: String,
+    period: String,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            name: Column::Header(String::from("name")),
+            secret: Column::Header(String::from("secret")),
+            issuer: None,
+            digits: None,
+            period: None,
+        }
+    }
+}
+
+impl FromStr for Columns {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns: Self = Default::default();
+
+        for mapping in s.split(',').filter(|mapping| !mapping.trim().is_empty()) {
+            let (field, column) = match mapping.split_once('=') {
+                Some((field, column)) => (field.trim(), column.trim()),
+                None => return Err(format!("Mapping must be FIELD=COLUMN: {}", mapping)),
+            };
+            let column = match column.parse::<usize>() {
+                Ok(0) => return Err(String::from("Column index starts from 1.")),
+                Ok(index) => Column::Index(index),
+                Err(_) => Column::Header(column.to_string()),
+            };
+
+            match field.to_lowercase().as_str() {
+                "name" => columns.name = column,
+                "secret" => columns.secret = column,
+                "issuer" => columns.issuer = Some(column),
+                "digits" => columns.digits = Some(column),
+                "period" => columns.period = Some(column),
+                _ => {
+                    return Err(format!(
+                        "Unknown field: {}. Fields are name, secret, issuer, digits and period.",
+                        field
+                    ))
+                }
+            }
+        }
+
+        Ok(columns)
+    }
+}
+
+impl Column {
+    // Get the 0-based index of the column.
+    fn resolve(&self, headers: &csv::StringRecord) -> Result<usize, String> {
+        match self {
+            Self::Index(index) => Ok(index - 1),
+            Self::Header(name) => match headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name))
+            {
+                Some(index) => Ok(index),
+                None => Err(format!("Column {} is not found in the header.", name)),
+            },
+        }
+    }
+}
+
+// Parse CSV which has a header row.
+pub fn parse(data: &[u8], columns: &Columns) -> Result<Vec<Row>, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+    let headers = match reader.headers() {
+        Ok(headers) => headers.clone(),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let name = columns.name.resolve(&headers)?;
+    let secret = columns.secret.resolve(&headers)?;
+    let resolve = |column: &Option<Column>| match column {
+        Some(column) => column.resolve(&headers).map(Some),
+        None => Ok(None),
+    };
+    let issuer = resolve(&columns.issuer)?;
+    let digits = resolve(&columns.digits)?;
+    let period = resolve(&columns.period)?;
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => return Err(err.to_string()),
+        };
+        let field = |index: Option<usize>| match index.and_then(|index| record.get(index)) {
+            Some(value) => value.trim().to_string(),
+            None => String::new(),
+        };
+
+        rows.push(Row {
+            line: record.position().map_or(0, |position| position.line()),
+            name: field(Some(name)),
+            secret: field(Some(secret)),
+            issuer: field(issuer),
+            digits: field(digits),
+            period: field(period),
+        });
+    }
+
+    Ok(rows)
+}
+
+impl import::Entry for Row {
+    // A label to show the row without the secret. "Issuer:Name (line N)"
+    fn label(&self) -> String {
+        if self.issuer.is_empty() {
+            return format!("{} (line {})", self.name, self.line);
+        }
+
+        format!("{}:{} (line {})", self.issuer, self.name, self.line)
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the issuer and the name like otpauth URI.
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        if self.name.is_empty() {
+            return Err(ValidationError::Requires("Name must be present."));
+        }
+        if self.secret.is_empty() {
+            return Err(ValidationError::Requires("Secret must be present."));
+        }
+
+        let name = import::profile_name(&self.issuer, &self.name);
+        let mut profile = Profile::new(&name, &otpauth::normalize_secret(&self.secret)?);
+        profile.set_issuer(&self.issuer);
+        profile.set_account(&self.name);
+        if !self.digits.is_empty() {
+            match self.digits.parse::<u8>() {
+                Ok(digits) => profile.set_digits(digits),
+                Err(_) => return Err(ValidationError::InvalidFormat("Digits must be a number.")),
+            }
+        }
+        if !self.period.is_empty() {
+            match self.period.parse::<u64>() {
+                Ok(period) => profile.set_period(period),
+                Err(_) => return Err(ValidationError::InvalidFormat("Period must be a number.")),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    const CSV: &[u8] = include_bytes!("../tests/fixtures/accounts.csv");
+
+    #[test]
+    fn parse_columns() {
+        let columns = "name=Username, secret=3, issuer=Title".parse::<Columns>();
+
+        assert_eq!(
+            columns,
+            Ok(Columns {
+                name: Column::Header(String::from("Username")),
+                secret: Column::Index(3),
+                issuer: Some(Column::Header(String::from("Title"))),
+                digits: None,
+                period: None,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid_columns() {
+        assert!("name".parse::<Columns>().is_err());
+        assert!("name=0".parse::<Columns>().is_err());
+        assert!("label=Title".parse::<Columns>().is_err());
+    }
+
+    #[test]
+    fn parse_csv() {
+        let columns = "name=Username,secret=Seed,issuer=Title,digits=Digits,period=Period"
+            .parse::<Columns>()
+            .unwrap();
+        let profiles: Vec<Result<Profile, ValidationError>> = parse(CSV, &columns)
+            .unwrap()
+            .iter()
+            .map(|row| row.to_profile())
+            .collect();
+
+        let mut github = Profile::new("GitHub-alice", "JBSWY3DPEHPK3PXP");
+        github.set_issuer("GitHub");
+        github.set_account("alice");
+        let mut example = Profile::new("Example-bob", "GEZDGNBVGY3TQOJQ");
+        example.set_issuer("Example");
+        example.set_account("bob");
+        example.set_digits(8);
+        example.set_period(60);
+        assert_eq!(
+            profiles,
+            vec![
+                Ok(github),
+                Ok(example),
+                Err(ValidationError::InvalidFormat("Digits must be a number.")),
+                Err(ValidationError::Requires("Secret must be present.")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_csv_with_invalid_secret() {
+        let csv = b"name,secret\nbadone,not*base32!\ngood,gezd gnbv gy3t qojq gezd gnbv gy==\n";
+        let rows = parse(csv, &Default::default()).unwrap();
+
+        assert_eq!(
+            rows[0].to_profile(),
+            Err(ValidationError::InvalidFormat(
+                "Secret must be encoded in base32."
+            ))
+        );
+        let profile = rows[1].to_profile().unwrap();
+        let mut expected = Profile::new("good", "GEZDGNBVGY3TQOJQGEZDGNBVGY");
+        expected.set_account("good");
+        assert_eq!(profile, expected);
+        assert!(profile.get_secret().is_some());
+    }
+
+    #[test]
+    fn parse_csv_without_column() {
+        assert_eq!(
+            parse(CSV, &Default::default()).unwrap_err(),
+            "Column name is not found in the header."
+        );
+    }
+
+    #[test]
+    fn label_has_line() {
+        let columns = "name=Username,secret=Seed,issuer=Title"
+            .parse::<Columns>()
+            .unwrap();
+        let rows = parse(CSV, &columns).unwrap();
+
+        assert_eq!(rows[0].label(), "GitHub:alice (line 2)");
+    }
+}
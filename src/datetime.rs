@@ -0,0 +1,104 @@
+extern crate toml;
+
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+use toml::value::{Date, Datetime, Offset, Time};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// The current time in UTC. Seconds are the finest unit.
+pub fn now() -> Datetime {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    from_unix(seconds)
+}
+
+// Build an offset date-time in UTC from the unix time.
+pub fn from_unix(seconds: u64) -> Datetime {
+    let seconds = seconds as i64;
+    let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+    let time = seconds.rem_euclid(SECONDS_PER_DAY);
+
+    Datetime {
+        date: Some(Date { year, month, day }),
+        time: Some(Time {
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+            nanosecond: 0,
+        }),
+        offset: Some(Offset::Z),
+    }
+}
+
+// The unix time of the date-time. A local date-time is regarded as UTC.
+//
+// It returns None if it has no date or it is before the epoch.
+pub fn to_unix(datetime: &Datetime) -> Option<u64> {
+    let date = datetime.date?;
+    let days = days_from_civil(date.year, date.month, date.day);
+    let mut seconds = days * SECONDS_PER_DAY;
+    if let Some(time) = datetime.time {
+        seconds += i64::from(time.hour) * 3600 + i64::from(time.minute) * 60;
+        seconds += i64::from(time.second);
+    }
+    if let Some(Offset::Custom { minutes }) = datetime.offset {
+        seconds -= i64::from(minutes) * 60;
+    }
+
+    u64::try_from(seconds).ok()
+}
+
+// 1970-01-01 からの日数を日付にする (Howard Hinnant のアルゴリズム)
+fn civil_from_days(days: i64) -> (u16, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year as u16, month as u8, day as u8)
+}
+
+fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    let year = i64::from(year) - i64::from(month <= 2);
+    let month = i64::from(month);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if 2 < month { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_unix_time() {
+        assert_eq!(from_unix(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(from_unix(951_782_400).to_string(), "2000-02-29T00:00:00Z");
+        assert_eq!(from_unix(1_792_242_804).to_string(), "2026-10-17T13:13:24Z");
+    }
+
+    #[test]
+    fn round_trip() {
+        for seconds in [0, 951_782_400, 1_792_242_804, 4_102_444_799] {
+            assert_eq!(to_unix(&from_unix(seconds)), Some(seconds));
+        }
+    }
+
+    #[test]
+    fn to_unix_with_offset() {
+        let datetime: Datetime = "2026-10-17T22:13:24+09:00".parse().unwrap();
+
+        assert_eq!(to_unix(&datetime), Some(1_792_242_804));
+    }
+}
@@ -0,0 +1,284 @@
+extern crate argon2;
+extern crate base64;
+extern crate toml;
+
+use super::crypto;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// 鍵導出関数の名前
+const KDF_ARGON2ID: &str = "argon2id";
+// データを暗号化する AEAD の名前
+const CIPHER_AES256_GCM: &str = "aes-256-gcm";
+// Argon2 の salt の長さ
+const SALT_LENGTH: usize = 16;
+
+// Argon2id parameters. The defaults follow the recommendation of OWASP.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct KdfParams {
+    pub memory: u32, // KiB
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+// The profile file encrypted with a passphrase
+//
+// The profiles are encrypted with a random data key.
+// The data key is wrapped with a key derived from the passphrase by Argon2id,
+// so that the passphrase can be changed without encrypting the profiles again.
+#[derive(Serialize, Deserialize, Debug)]
+struct EncryptedFile {
+    encryption: Header,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Header {
+    kdf: String,
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+    salt: String,
+    cipher: String,
+    key_nonce: String,
+    wrapped_key: String,
+}
+
+// The data key and how it is wrapped
+pub struct Key {
+    data_key: Vec<u8>,
+    header: Header,
+}
+
+// 鍵そのものは表示しない
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Key").finish_non_exhaustive()
+    }
+}
+
+// Whether the contents of the profile file are encrypted.
+pub fn is_encrypted(contents: &str) -> bool {
+    match contents.parse::<toml::Table>() {
+        Ok(table) => table.contains_key("encryption"),
+        Err(_) => false,
+    }
+}
+
+// Decrypt the contents of the profile file.
+//
+// It returns the key to encrypt the profiles again and the plaintext.
+pub fn open(contents: &str, passphrase: &str) -> Result<(Key, String), String> {
+    let file: EncryptedFile = match toml::from_str(contents) {
+        Ok(file) => file,
+        Err(_) => return Err(String::from("The encrypted profile file is broken.")),
+    };
+    if file.encryption.cipher != CIPHER_AES256_GCM {
+        return Err(String::from("Cipher must be aes-256-gcm."));
+    }
+
+    let key = Key::unwrap(file.encryption, passphrase)?;
+    let plaintext = match crypto::aes_gcm_decrypt(
+        &key.data_key,
+        &decode(&file.nonce)?,
+        &decode(&file.ciphertext)?,
+    ) {
+        Ok(plaintext) => plaintext,
+        Err(_) => return Err(String::from("The encrypted profile file is broken.")),
+    };
+
+    match String::from_utf8(plaintext) {
+        Ok(plaintext) => Ok((key, plaintext)),
+        Err(_) => Err(String::from("The encrypted profile file is broken.")),
+    }
+}
+
+impl Key {
+    // Generate a new data key and wrap it with the passphrase.
+    pub fn new(passphrase: &str) -> Result<Self, String> {
+        Self::with_params(passphrase, Default::default())
+    }
+
+    pub(crate) fn with_params(passphrase: &str, params: KdfParams) -> Result<Self, String> {
+        let data_key = crypto::random_bytes(crypto::KEY_LENGTH);
+        let header = wrap(&data_key, passphrase, params)?;
+
+        Ok(Self { data_key, header })
+    }
+
+    // Unwrap the data key with the passphrase.
+    fn unwrap(header: Header, passphrase: &str) -> Result<Self, String> {
+        if header.kdf != KDF_ARGON2ID {
+            return Err(String::from("KDF must be argon2id."));
+        }
+
+        let params = KdfParams {
+            memory: header.memory,
+            iterations: header.iterations,
+            parallelism: header.parallelism,
+        };
+        let wrapping_key = derive_key(passphrase, &decode(&header.salt)?, params)?;
+        match crypto::aes_gcm_decrypt(
+            &wrapping_key,
+            &decode(&header.key_nonce)?,
+            &decode(&header.wrapped_key)?,
+        ) {
+            Ok(data_key) => Ok(Self { data_key, header }),
+            Err(_) => Err(String::from("The passphrase is incorrect.")),
+        }
+    }
+
+    // Wrap the data key with a new passphrase.
+    //
+    // The profiles don't need to be encrypted again.
+    pub fn rewrap(&mut self, passphrase: &str) -> Result<(), String> {
+        let params = KdfParams {
+            memory: self.header.memory,
+            iterations: self.header.iterations,
+            parallelism: self.header.parallelism,
+        };
+        self.header = wrap(&self.data_key, passphrase, params)?;
+
+        Ok(())
+    }
+
+    // Encrypt the profiles and build the contents of the profile file.
+    pub fn seal(&self, plaintext: &str) -> Result<String, String> {
+        let nonce = crypto::random_bytes(crypto::NONCE_LENGTH);
+        let ciphertext = crypto::aes_gcm_encrypt(&self.data_key, &nonce, plaintext.as_bytes())?;
+
+        let file = EncryptedFile {
+            encryption: self.header.clone(),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        };
+        match toml::to_string(&file) {
+            Ok(contents) => Ok(contents),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+}
+
+fn wrap(data_key: &[u8], passphrase: &str, params: KdfParams) -> Result<Header, String> {
+    if passphrase.is_empty() {
+        return Err(String::from("The passphrase must not be empty."));
+    }
+
+    let salt = crypto::random_bytes(SALT_LENGTH);
+    let key_nonce = crypto::random_bytes(crypto::NONCE_LENGTH);
+    let wrapping_key = derive_key(passphrase, &salt, params)?;
+    let wrapped_key = crypto::aes_gcm_encrypt(&wrapping_key, &key_nonce, data_key)?;
+
+    Ok(Header {
+        kdf: KDF_ARGON2ID.to_string(),
+        memory: params.memory,
+        iterations: params.iterations,
+        parallelism: params.parallelism,
+        salt: BASE64.encode(salt),
+        cipher: CIPHER_AES256_GCM.to_string(),
+        key_nonce: BASE64.encode(key_nonce),
+        wrapped_key: BASE64.encode(wrapped_key),
+    })
+}
+
+// Argon2id で鍵を導出する
+fn derive_key(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<Vec<u8>, String> {
+    let params = match argon2::Params::new(
+        params.memory,
+        params.iterations,
+        params.parallelism,
+        Some(crypto::KEY_LENGTH),
+    ) {
+        Ok(params) => params,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut key = vec![0u8; crypto::KEY_LENGTH];
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    match argon2.hash_password_into(passphrase.as_bytes(), salt, &mut key) {
+        Ok(_) => Ok(key),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn decode(value: &str) -> Result<Vec<u8>, String> {
+    match BASE64.decode(value) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => Err(String::from("The encrypted profile file is broken.")),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // テストでは軽いパラメータを使う
+    pub(crate) const TEST_PARAMS: KdfParams = KdfParams {
+        memory: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    const PROFILES: &str = "[[profiles]]\nname = \"test\"\nsecret = \"JBSWY3DPEHPK3PXP\"\n";
+
+    #[test]
+    fn seal_and_open() {
+        let key = Key::with_params("passphrase", TEST_PARAMS).unwrap();
+        let contents = key.seal(PROFILES).unwrap();
+
+        assert!(is_encrypted(&contents));
+        assert!(!contents.contains("JBSWY3DPEHPK3PXP"));
+
+        let (_, plaintext) = open(&contents, "passphrase").unwrap();
+        assert_eq!(plaintext, PROFILES);
+    }
+
+    #[test]
+    fn open_with_wrong_passphrase() {
+        let key = Key::with_params("passphrase", TEST_PARAMS).unwrap();
+        let contents = key.seal(PROFILES).unwrap();
+
+        assert_eq!(
+            open(&contents, "wrong").unwrap_err(),
+            "The passphrase is incorrect."
+        );
+    }
+
+    #[test]
+    fn rewrap_keeps_data_key() {
+        let mut key = Key::with_params("old", TEST_PARAMS).unwrap();
+        let data_key = key.data_key.clone();
+        key.rewrap("new").unwrap();
+
+        let contents = key.seal(PROFILES).unwrap();
+        assert!(open(&contents, "old").is_err());
+
+        let (key, plaintext) = open(&contents, "new").unwrap();
+        assert_eq!(key.data_key, data_key);
+        assert_eq!(plaintext, PROFILES);
+    }
+
+    #[test]
+    fn empty_passphrase() {
+        assert!(Key::with_params("", TEST_PARAMS).is_err());
+    }
+
+    #[test]
+    fn plaintext_is_not_encrypted() {
+        assert!(!is_encrypted(PROFILES));
+        assert!(!is_encrypted(""));
+    }
+}
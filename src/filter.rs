@@ -0,0 +1,159 @@
+extern crate regex;
+extern crate serde;
+
+use super::config::Profile;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Conditions to choose profiles.
+//
+// A profile is chosen if it has all of the tags and the name matches the glob and the regex.
+// Empty conditions choose all of profiles.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    glob: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+}
+
+impl Filter {
+    pub fn new(tags: &[String], glob: Option<&str>, regex: Option<&str>) -> Self {
+        Self {
+            tags: tags.to_vec(),
+            glob: glob.map(String::from),
+            regex: regex.map(String::from),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.glob.is_none() && self.regex.is_none()
+    }
+
+    // Choose profiles which match the conditions. The order is kept.
+    pub fn apply<'a, I>(&self, profiles: I) -> Result<Vec<&'a Profile>, String>
+    where
+        I: IntoIterator<Item = &'a Profile>,
+    {
+        let mut patterns = Vec::new();
+        if let Some(glob) = &self.glob {
+            patterns.push(glob_to_regex(glob));
+        }
+        if let Some(regex) = &self.regex {
+            match Regex::new(regex) {
+                Ok(regex) => patterns.push(regex),
+                Err(err) => return Err(format!("Invalid regex: {}", err)),
+            }
+        }
+
+        Ok(profiles
+            .into_iter()
+            .filter(|profile| self.tags.iter().all(|tag| profile.get_tags().contains(tag)))
+            .filter(|profile| {
+                patterns
+                    .iter()
+                    .all(|pattern| pattern.is_match(profile.get_name()))
+            })
+            .collect())
+    }
+}
+
+// Convert a glob to an anchored regex. `*` matches any characters and `?` matches a character.
+fn glob_to_regex(glob: &str) -> Regex {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push_str("\\z");
+
+    // 記号はすべてエスケープしているので失敗しない
+    Regex::new(&pattern).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles() -> Vec<Profile> {
+        let mut github = Profile::new("github-work", "a");
+        github.add_tag("work");
+        github.add_tag("git");
+        let mut gitlab = Profile::new("gitlab", "a");
+        gitlab.add_tag("git");
+        let bank = Profile::new("bank", "a");
+        vec![github, gitlab, bank]
+    }
+
+    fn names(profiles: Vec<&Profile>) -> Vec<&str> {
+        profiles
+            .iter()
+            .map(|profile| profile.get_name().as_str())
+            .collect()
+    }
+
+    #[test]
+    fn empty_filter_chooses_all() {
+        let profiles = profiles();
+        let filter: Filter = Default::default();
+
+        assert!(filter.is_empty());
+        assert_eq!(
+            names(filter.apply(&profiles).unwrap()),
+            vec!["github-work", "gitlab", "bank"]
+        );
+    }
+
+    #[test]
+    fn filter_by_tags() {
+        let profiles = profiles();
+        let git = Filter::new(&[String::from("git")], None, None);
+        let git_work = Filter::new(&[String::from("git"), String::from("work")], None, None);
+
+        assert_eq!(
+            names(git.apply(&profiles).unwrap()),
+            vec!["github-work", "gitlab"]
+        );
+        assert_eq!(
+            names(git_work.apply(&profiles).unwrap()),
+            vec!["github-work"]
+        );
+    }
+
+    #[test]
+    fn filter_by_glob() {
+        let profiles = profiles();
+
+        let filter = Filter::new(&[], Some("git*"), None);
+        assert_eq!(
+            names(filter.apply(&profiles).unwrap()),
+            vec!["github-work", "gitlab"]
+        );
+        let filter = Filter::new(&[], Some("b?nk"), None);
+        assert_eq!(names(filter.apply(&profiles).unwrap()), vec!["bank"]);
+        // 全体に一致しなければ選ばない
+        let filter = Filter::new(&[], Some("git"), None);
+        assert!(filter.apply(&profiles).unwrap().is_empty());
+    }
+
+    #[test]
+    fn filter_by_regex() {
+        let profiles = profiles();
+
+        let filter = Filter::new(&[], None, Some("lab$|^ba"));
+        assert_eq!(
+            names(filter.apply(&profiles).unwrap()),
+            vec!["gitlab", "bank"]
+        );
+        let filter = Filter::new(&[String::from("work")], None, Some("lab$"));
+        assert!(filter.apply(&profiles).unwrap().is_empty());
+        let filter = Filter::new(&[], None, Some("("));
+        assert!(filter.apply(&profiles).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+// Fuzzy matching of profile names
+//
+// A query matches a name if the characters of the query appear in the name in order.
+// e.g. "gh" matches "github-work"
+
+// 一致した文字ごとの点数
+const MATCH_SCORE: i64 = 10;
+// 直前の文字から続けて一致したときの加点
+const CONSECUTIVE_BONUS: i64 = 5;
+// 単語の先頭で一致したときの加点
+const WORD_START_BONUS: i64 = 8;
+// 一致の間で読み飛ばした文字ごとの減点
+const GAP_PENALTY: i64 = 1;
+
+// Score how well the query matches the name. None if it doesn't match.
+//
+// Consecutive matches and matches at the start of words (after -, _ or @) get more points.
+// Case is ignored.
+pub fn score(query: &str, name: &str) -> Option<i64> {
+    let name: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut last: Option<usize> = None;
+    let mut position = 0;
+
+    for q in query.chars() {
+        let index = (position..name.len()).find(|&i| eq_ignore_case(name[i], q))?;
+
+        score += MATCH_SCORE;
+        if index == 0 || matches!(name[index - 1], '-' | '_' | '@') {
+            score += WORD_START_BONUS;
+        }
+        match last {
+            Some(last) if last + 1 == index => score += CONSECUTIVE_BONUS,
+            Some(last) => score -= (index - last - 1) as i64 * GAP_PENALTY,
+            None => score -= index as i64 * GAP_PENALTY,
+        }
+
+        last = Some(index);
+        position = index + 1;
+    }
+
+    Some(score)
+}
+
+// Names which match the query, from the best. Shorter names come first for the same score.
+pub fn rank<'a>(query: &str, names: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut scored: Vec<(i64, &str)> = names
+        .into_iter()
+        .filter_map(|name| score(query, name).map(|score| (score, name)))
+        .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then(a.len().cmp(&b.len()))
+            .then(a.cmp(b))
+    });
+
+    scored.into_iter().map(|(_, name)| name).collect()
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a.to_lowercase().eq(b.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_subsequence() {
+        assert!(score("gh", "github-work").is_some());
+        assert!(score("GW", "github-work").is_some());
+        assert!(score("hg", "github-work").is_none());
+        assert!(score("gitlab", "git").is_none());
+        assert_eq!(score("", "github"), Some(0));
+    }
+
+    #[test]
+    fn score_prefers_consecutive_and_word_starts() {
+        let prefix = score("git", "github").unwrap();
+        let scattered = score("git", "greenlight").unwrap();
+        assert!(prefix > scattered);
+
+        let word_start = score("w", "github-work").unwrap();
+        let middle = score("w", "gitlab-aws").unwrap();
+        assert!(word_start > middle);
+    }
+
+    #[test]
+    fn rank_names() {
+        let names = vec!["gitlab", "github-work", "github", "bank"];
+
+        assert_eq!(
+            rank("git", names.clone()),
+            vec!["github", "gitlab", "github-work"]
+        );
+        assert_eq!(rank("gw", names.clone()), vec!["github-work"]);
+        assert!(rank("xyz", names).is_empty());
+    }
+}
@@ -0,0 +1,70 @@
+extern crate digest;
+extern crate hmac;
+extern crate sha2;
+
+use digest::generic_array::typenum::{U32, U64};
+use digest::generic_array::GenericArray;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+
+type HmacSha256 = Hmac<Sha256>;
+type HmacSha512 = Hmac<Sha512>;
+
+// HMAC-SHA-256 を計算する
+pub fn gen_hmac_sha256(key: &[u8], input: &[u8]) -> Result<GenericArray<u8, U32>, String> {
+    let mut mac = match HmacSha256::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(err) => return Err(format!("{}", err)),
+    };
+
+    mac.update(input);
+
+    Ok(mac.finalize().into_bytes())
+}
+
+// HMAC-SHA-512 を計算する
+pub fn gen_hmac_sha512(key: &[u8], input: &[u8]) -> Result<GenericArray<u8, U64>, String> {
+    let mut mac = match HmacSha512::new_from_slice(key) {
+        Ok(mac) => mac,
+        Err(err) => return Err(format!("{}", err)),
+    };
+
+    mac.update(input);
+
+    Ok(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_hex(bytes: &[u8]) -> String {
+        let mut result = String::from("");
+
+        for c in bytes.iter() {
+            result.push_str(&format!("{:02x}", c))
+        }
+
+        result
+    }
+
+    // from RFC4231 Test Case 2
+    #[test]
+    fn rfc_4231_hmacsha256_2() {
+        let code = gen_hmac_sha256(b"Jefe", b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            to_hex(&code),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        )
+    }
+
+    #[test]
+    fn rfc_4231_hmacsha512_2() {
+        let code = gen_hmac_sha512(b"Jefe", b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            to_hex(&code),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554\
+             9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        )
+    }
+}
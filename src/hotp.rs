@@ -1,12 +1,10 @@
-use super::hmac_sha1;
-use digest::generic_array::typenum::U20;
-use digest::generic_array::GenericArray;
-
-type OutputSize = U20;
+use super::algorithm::Algorithm;
+use byteorder::{BigEndian, WriteBytesExt};
 
 /// Generating HOTP function
 ///
 /// Step 1: Generate an HMAC-SHA-1 value Let HS = HMAC-SHA-1(K,C)  // HS is a 20-byte string
+///         (HMAC-SHA-256 and HMAC-SHA-512 are also allowed as described in RFC 6238)
 ///
 /// Step 2: Generate a 4-byte string (Dynamic Truncation)
 /// Let Sbits = DT(HS)   //  DT, defined below,
@@ -15,21 +13,53 @@ type OutputSize = U20;
 /// Step 3: Compute an HOTP value
 /// Let Snum  = StToNum(Sbits)   // Convert S to a number in 0...2^{31}-1
 /// Return D = Snum mod 10^Digit //  D is a number in the range 0...10^{Digit}-1
-pub fn hotp(secret: &[u8], counter: &[u8], digits: u8) -> Result<String, String> {
-    let hmac = match hmac_sha1::gen_hmac_sha1(secret, counter) {
+pub fn hotp(
+    secret: &[u8],
+    counter: &[u8],
+    digits: u8,
+    algorithm: Algorithm,
+) -> Result<String, String> {
+    let hmac = match algorithm.hmac(secret, counter) {
         Ok(hmac) => hmac,
         Err(err) => return Err(err),
     };
-    let sbits = truncate(hmac);
+    let sbits = truncate(&hmac);
 
     bit_to_decimal_code(sbits, digits)
 }
 
+// HOTP を任意のカウンター値で計算する
+pub fn gen_hotp(
+    secret: &[u8],
+    counter: u64,
+    digits: u8,
+    algorithm: Algorithm,
+) -> Result<String, String> {
+    let mut byte_c = Vec::new();
+    byte_c.write_u64::<BigEndian>(counter).unwrap();
+
+    hotp(secret, &byte_c, digits, algorithm)
+}
+
+// Steam Guard のコードに使う文字
+const STEAM_CHARS: &[u8] = b"23456789BCDFGHJKMNPQRTVWXY";
+// Steam Guard のコードの長さ
+const STEAM_CODE_LENGTH: usize = 5;
+
+// HOTP encoded with Steam Guard characters instead of decimal digits
+pub fn steam(secret: &[u8], counter: &[u8], algorithm: Algorithm) -> Result<String, String> {
+    let hmac = match algorithm.hmac(secret, counter) {
+        Ok(hmac) => hmac,
+        Err(err) => return Err(err),
+    };
+
+    Ok(bit_to_steam_code(truncate(&hmac)))
+}
+
 // Dynamic Truncate
-fn truncate(hmac: GenericArray<u8, OutputSize>) -> u32 {
-    let hash = hmac.as_slice();
-    let len = hash.len();
-    let offset: usize = (hash[len - 1] & 0x0f) as usize;
+fn truncate(hmac: &[u8]) -> u32 {
+    let len = hmac.len();
+    let offset: usize = (hmac[len - 1] & 0x0f) as usize;
 
     let mut result: u32 = 0;
     result |= (((hmac[offset] as u32) & 0x7f) << 24)
@@ -50,6 +80,19 @@ fn bit_to_decimal_code(sbits: u32, digits: u8) -> Result<String, String> {
     Ok(zero_padding(code, digits as usize))
 }
 
+// Steam Guard の文字に変換する
+fn bit_to_steam_code(sbits: u32) -> String {
+    let mut value = sbits as usize;
+    let mut code = String::new();
+
+    for _ in 0..STEAM_CODE_LENGTH {
+        code.push(STEAM_CHARS[value % STEAM_CHARS.len()] as char);
+        value /= STEAM_CHARS.len();
+    }
+
+    code
+}
+
 // 文字を左から 0埋めする
 fn zero_padding(string: String, length: usize) -> String {
     let mut value = string;
@@ -70,6 +113,12 @@ mod tests {
         assert_eq!(zero_padding("1".to_string(), 5), "00001")
     }
 
+    #[test]
+    fn to_steam_code() {
+        assert_eq!(bit_to_steam_code(0), "22222");
+        assert_eq!(bit_to_steam_code(27), "33222");
+    }
+
     #[test]
     fn to_decima_too_small() {
         assert!(bit_to_decimal_code(0, 0).is_err())
@@ -81,33 +130,33 @@ mod tests {
 
     #[test]
     fn truncate_test() {
-        let arr = GenericArray::from([
+        let arr = [
             0x00_u8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xbb, 0xbb, 0xbb,
             0x00, 0x00, 0x00, 0x00, 0x00, 0x0a,
-        ]);
-        let result = truncate(arr);
+        ];
+        let result = truncate(&arr);
 
         assert_eq!(result, 0x7f_bb_bb_bb);
     }
 
     #[test]
     fn rfc_4226_truncate_0() {
-        let hmac_sha1 = GenericArray::from([
+        let hmac_sha1 = [
             0xcc_u8, 0x93, 0xcf, 0x18, 0x50, 0x8d, 0x94, 0x93, 0x4c, 0x64, 0xb6, 0x5d, 0x8b, 0xa7,
             0x66, 0x7f, 0xb7, 0xcd, 0xe4, 0xb0,
-        ]);
+        ];
 
-        assert_eq!(truncate(hmac_sha1), 0x4c93_cf18);
+        assert_eq!(truncate(&hmac_sha1), 0x4c93_cf18);
     }
 
     #[test]
     fn rfc_4226_truncate_1() {
-        let hmac_sha1 = GenericArray::from([
+        let hmac_sha1 = [
             0x75_u8, 0xa4, 0x8a, 0x19, 0xd4, 0xcb, 0xe1, 0x00, 0x64, 0x4e, 0x8a, 0xc1, 0x39, 0x7e,
             0xea, 0x74, 0x7a, 0x2d, 0x33, 0xab,
-        ]);
+        ];
 
-        assert_eq!(truncate(hmac_sha1), 0x4139_7eea);
+        assert_eq!(truncate(&hmac_sha1), 0x4139_7eea);
     }
 
     #[test]
@@ -125,13 +174,35 @@ mod tests {
 
     #[test]
     fn rfc_4226_hotp_4() {
-        let code = hotp(b"12345678901234567890", &[0_u8, 0, 0, 0, 0, 0, 0, 4], 6);
+        let code = hotp(
+            b"12345678901234567890",
+            &[0_u8, 0, 0, 0, 0, 0, 0, 4],
+            6,
+            Algorithm::Sha1,
+        );
         assert_eq!(code, Ok("338314".to_string()));
     }
 
     #[test]
     fn rfc_4226_hotp_5() {
-        let code = hotp(b"12345678901234567890", &[0_u8, 0, 0, 0, 0, 0, 0, 5], 6);
+        let code = hotp(
+            b"12345678901234567890",
+            &[0_u8, 0, 0, 0, 0, 0, 0, 5],
+            6,
+            Algorithm::Sha1,
+        );
         assert_eq!(code, Ok("254676".to_string()));
     }
+
+    #[test]
+    fn rfc_4226_gen_hotp_0() {
+        let code = gen_hotp(b"12345678901234567890", 0, 6, Algorithm::Sha1);
+        assert_eq!(code, Ok("755224".to_string()));
+    }
+
+    #[test]
+    fn rfc_4226_gen_hotp_9() {
+        let code = gen_hotp(b"12345678901234567890", 9, 6, Algorithm::Sha1);
+        assert_eq!(code, Ok("520489".to_string()));
+    }
 }
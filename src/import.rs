@@ -0,0 +1,300 @@
+use super::config::{Profile, ProfileType, ValidationError};
+use super::migration;
+use super::otpauth::KeyUri;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const OTPAUTH_SCHEME: &str = "otpauth://";
+const MIGRATION_SCHEME: &str = "otpauth-migration://";
+
+// An entry of an export of another authenticator
+pub trait Entry {
+    // A label to show the entry without the secret
+    fn label(&self) -> String;
+
+    fn to_profile(&self) -> Result<Profile, ValidationError>;
+}
+
+// 取り込み対象のプロファイル
+// 取り込めないものは理由と一緒に Skipped になる
+pub type Candidate = Result<Profile, Skipped>;
+
+// 取り込まなかったもの
+#[derive(Debug, PartialEq)]
+pub struct Skipped {
+    label: String,
+    reason: String,
+}
+
+impl Skipped {
+    pub fn new(label: &str, reason: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn get_label(&self) -> &String {
+        &self.label
+    }
+
+    pub fn get_reason(&self) -> &String {
+        &self.reason
+    }
+}
+
+impl fmt::Display for Skipped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.label, self.reason)
+    }
+}
+
+// 取り込み結果
+#[derive(Debug, Default)]
+pub struct Report {
+    imported: Vec<String>,
+    renamed: Vec<(String, String)>,
+    skipped: Vec<Skipped>,
+}
+
+impl Report {
+    pub fn push_imported(&mut self, name: &str) {
+        self.imported.push(name.to_string());
+    }
+
+    // Record that a profile is imported with another name because of a name collision.
+    pub fn push_renamed(&mut self, from: &str, to: &str) {
+        self.imported.push(to.to_string());
+        self.renamed.push((from.to_string(), to.to_string()));
+    }
+
+    pub fn push_skipped(&mut self, skipped: Skipped) {
+        self.skipped.push(skipped);
+    }
+
+    // Names of imported profiles
+    pub fn get_imported(&self) -> &Vec<String> {
+        &self.imported
+    }
+
+    pub fn get_skipped(&self) -> &Vec<Skipped> {
+        &self.skipped
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Imported {} profile(s)", self.imported.len())?;
+        for name in &self.imported {
+            match self.renamed.iter().find(|(_, to)| to == name) {
+                Some((from, _)) => writeln!(f, "  {} (renamed from {})", name, from)?,
+                None => writeln!(f, "  {}", name)?,
+            }
+        }
+
+        if !self.skipped.is_empty() {
+            writeln!(f, "Skipped {} profile(s)", self.skipped.len())?;
+            for skipped in &self.skipped {
+                writeln!(f, "  {}", skipped)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Name a profile like "Issuer-account". Either of them can be empty.
+pub fn profile_name(issuer: &str, account: &str) -> String {
+    match (issuer.trim(), account.trim()) {
+        (issuer, "") => Profile::sanitize_name(issuer),
+        ("", account) => Profile::sanitize_name(account),
+        (issuer, account) => Profile::sanitize_name(&format!("{}-{}", issuer, account)),
+    }
+}
+
+// Set the digits of an imported profile.
+//
+// The digits of a Steam profile are ignored because Steam Guard codes always have 5 characters.
+pub fn set_digits(profile: &mut Profile, digits: Option<u8>) {
+    if profile.get_type() == ProfileType::Steam {
+        return;
+    }
+    if let Some(digits) = digits {
+        profile.set_digits(digits);
+    }
+}
+
+// Build candidates from a URI.
+//
+// The source is used as a label when the URI can't be parsed,
+// so that the secret in the URI is never printed.
+pub fn candidates_from_uri(source: &str, uri: &str) -> Vec<Candidate> {
+    if has_scheme(uri, MIGRATION_SCHEME) {
+        return match migration::Payload::parse(uri) {
+            Ok(payload) => candidates_from_migration(&payload),
+            Err(err) => vec![Err(Skipped::new(source, &err.to_string()))],
+        };
+    }
+    if !has_scheme(uri, OTPAUTH_SCHEME) {
+        return vec![Err(Skipped::new(source, "It is not an otpauth URI."))];
+    }
+
+    match KeyUri::parse(uri) {
+        Ok(key_uri) => vec![Ok(key_uri.to_profile())],
+        Err(err) => vec![Err(Skipped::new(source, &err.to_string()))],
+    }
+}
+
+// Build candidates from URIs.
+//
+// The pair is a source label and a URI.
+// Batches of Google Authenticator exports which are not given are reported as skipped.
+pub fn candidates_from_uris(uris: &[(String, String)]) -> Vec<Candidate> {
+    let mut candidates = Vec::new();
+    // batch_id => (batch_size, given batch indexes)
+    let mut batches: BTreeMap<u64, (u64, Vec<u64>)> = BTreeMap::new();
+
+    for (source, uri) in uris {
+        if has_scheme(uri, MIGRATION_SCHEME) {
+            if let Ok(payload) = migration::Payload::parse(uri) {
+                let batch = batches
+                    .entry(payload.get_batch_id())
+                    .or_insert((payload.get_batch_size(), Vec::new()));
+                batch.1.push(payload.get_batch_index());
+            }
+        }
+
+        candidates.extend(candidates_from_uri(source, uri));
+    }
+
+    for (batch_id, (batch_size, indexes)) in batches {
+        let missing: Vec<String> = (0..batch_size)
+            .filter(|index| !indexes.contains(index))
+            .map(|index| format!("{}/{}", index + 1, batch_size))
+            .collect();
+
+        if !missing.is_empty() {
+            candidates.push(Err(Skipped::new(
+                &format!("Google Authenticator export {}", batch_id),
+                &format!(
+                    "QR code {} is not given. Import all of QR codes of the export.",
+                    missing.join(", ")
+                ),
+            )));
+        }
+    }
+
+    candidates
+}
+
+// Build candidates from entries of an export of another authenticator.
+pub fn candidates_from_entries<E: Entry>(entries: &[E]) -> Vec<Candidate> {
+    entries
+        .iter()
+        .map(|entry| match entry.to_profile() {
+            Ok(profile) => Ok(profile),
+            Err(err) => Err(Skipped::new(&entry.label(), &err.to_string())),
+        })
+        .collect()
+}
+
+// Build candidates from entries of a Google Authenticator export.
+fn candidates_from_migration(payload: &migration::Payload) -> Vec<Candidate> {
+    candidates_from_entries(payload.get_entries())
+}
+
+// Whether the URI starts with the scheme. Case is ignored.
+pub fn has_scheme(uri: &str, scheme: &str) -> bool {
+    match uri.get(..scheme.len()) {
+        Some(prefix) => prefix.eq_ignore_ascii_case(scheme),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidates_from_otpauth_uri() {
+        let candidates = candidates_from_uri(
+            "qr.png",
+            "otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP",
+        );
+
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].as_ref().unwrap().get_name(), "Example-alice");
+    }
+
+    #[test]
+    fn candidates_from_invalid_uri() {
+        let candidates = candidates_from_uri("qr.png", "otpauth://totp/alice?secret=189");
+
+        assert_eq!(
+            candidates,
+            vec![Err(Skipped::new(
+                "qr.png",
+                "Secret must be encoded in base32."
+            ))]
+        );
+    }
+
+    #[test]
+    fn candidates_from_not_otpauth_uri() {
+        let candidates = candidates_from_uri("qr.png", "https://example.com");
+
+        assert_eq!(
+            candidates,
+            vec![Err(Skipped::new("qr.png", "It is not an otpauth URI."))]
+        );
+    }
+
+    #[test]
+    fn candidates_from_migration_uri() {
+        let uri = "otpauth-migration://offline?data=CjUKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZSABKAEwAgojChQxMjM0NTY3ODkwMTIzNDU2Nzg5MBIDYm9iIAIoAjABOAUQARgBIAAouWA%3D";
+        let candidates = candidates_from_uri("qr.png", uri);
+
+        let names: Vec<&String> = candidates
+            .iter()
+            .map(|candidate| candidate.as_ref().unwrap().get_name())
+            .collect();
+        assert_eq!(names, vec!["Example-alice@google", "bob"]);
+    }
+
+    #[test]
+    fn candidates_from_uris_with_missing_batch() {
+        // batch 1/2 of the export 777
+        let uri = "otpauth-migration://offline?data=CjUKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZSABKAEwAhABGAIgACiJBg%3D%3D";
+        let candidates = candidates_from_uris(&[("qr.png".to_string(), uri.to_string())]);
+
+        assert_eq!(candidates.len(), 2);
+        assert!(candidates[0].is_ok());
+        assert_eq!(
+            candidates[1],
+            Err(Skipped::new(
+                "Google Authenticator export 777",
+                "QR code 2/2 is not given. Import all of QR codes of the export."
+            ))
+        );
+    }
+
+    #[test]
+    fn profile_name_from_issuer_and_account() {
+        assert_eq!(profile_name("GitHub", "alice"), "GitHub-alice");
+        assert_eq!(profile_name("", " alice "), "alice");
+        assert_eq!(profile_name("GitHub", ""), "GitHub");
+    }
+
+    #[test]
+    fn set_digits_except_steam() {
+        let mut totp = Profile::new("totp", "JBSWY3DPEHPK3PXP");
+        set_digits(&mut totp, Some(8));
+        set_digits(&mut totp, None);
+        assert_eq!(totp.get_digits(), 8);
+
+        let mut steam = Profile::new("steam", "JBSWY3DPEHPK3PXP");
+        steam.set_type(ProfileType::Steam);
+        set_digits(&mut steam, Some(8));
+        assert_eq!(steam.get_digits(), Profile::new("", "").get_digits());
+    }
+}
@@ -0,0 +1,907 @@
+extern crate aes;
+extern crate argon2;
+extern crate base64;
+extern crate cbc;
+extern crate chacha20;
+extern crate flate2;
+extern crate hmac;
+extern crate roxmltree;
+
+use super::algorithm::Algorithm;
+use super::config::{Profile, ProfileType, ValidationError};
+use super::import;
+use super::otpauth::{self, KeyUri};
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Read;
+
+const SIGNATURE_1: u32 = 0x9AA2_D903;
+const SIGNATURE_2: u32 = 0xB54B_FB67;
+// KDBX のメジャーバージョン
+const MAJOR_VERSION: u16 = 4;
+
+// 外側のヘッダーのフィールド
+const HEADER_END: u8 = 0;
+const HEADER_CIPHER_ID: u8 = 2;
+const HEADER_COMPRESSION: u8 = 3;
+const HEADER_MASTER_SEED: u8 = 4;
+const HEADER_ENCRYPTION_IV: u8 = 7;
+const HEADER_KDF_PARAMETERS: u8 = 11;
+
+// 内側のヘッダーのフィールド
+const INNER_HEADER_END: u8 = 0;
+const INNER_HEADER_STREAM_ID: u8 = 1;
+const INNER_HEADER_STREAM_KEY: u8 = 2;
+
+// 保護された値を復号するストリーム
+const INNER_STREAM_CHACHA20: u32 = 3;
+
+const CIPHER_AES256: [u8; 16] = [
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+const CIPHER_CHACHA20: [u8; 16] = [
+    0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a,
+];
+const KDF_AES: [u8; 16] = [
+    0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea,
+];
+const KDF_ARGON2D: [u8; 16] = [
+    0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c,
+];
+const KDF_ARGON2ID: [u8; 16] = [
+    0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6,
+];
+
+// KeePassXC の TOTP Settings で Steam Guard を表す桁数
+const STEAM_SETTINGS: &str = "S";
+// 受け付ける KDF のパラメーターの上限
+// 壊れたファイルで鍵の導出が終わらなくなったり、巨大なメモリを確保したりしないようにする
+const AES_KDF_MAX_ROUNDS: u64 = 100_000_000;
+// bytes
+const ARGON2_MAX_MEMORY: u64 = 1 << 30;
+const ARGON2_MAX_ITERATIONS: u64 = 1_000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Keys to open a KDBX file. Either or both of the password and the key file are required.
+#[derive(Debug, Default)]
+pub struct Key {
+    password: Option<String>,
+    key_file: Option<Vec<u8>>,
+}
+
+// An entry of a KDBX database which has OTP attributes
+//
+// "otp" is an otpauth URI (KeePassXC) or "key=SEED&step=30&size=6" (KeeOtp).
+// "TOTP Seed" and "TOTP Settings" ("period;digits") are the legacy attributes of KeePassXC.
+#[derive(Debug, Default, PartialEq)]
+pub struct Entry {
+    title: String,
+    username: String,
+    otp: Option<String>,
+    totp_seed: Option<String>,
+    totp_settings: Option<String>,
+}
+
+impl Key {
+    pub fn new(password: Option<&str>, key_file: Option<Vec<u8>>) -> Self {
+        Self {
+            password: password.map(|password| password.to_string()),
+            key_file,
+        }
+    }
+
+    // SHA-256 of the hashes of the password and the key file
+    fn composite(&self) -> Result<Vec<u8>, String> {
+        if self.password.is_none() && self.key_file.is_none() {
+            return Err(String::from("The password or the key file is required."));
+        }
+
+        let mut hasher = Sha256::new();
+        if let Some(password) = &self.password {
+            hasher.update(Sha256::digest(password.as_bytes()));
+        }
+        if let Some(key_file) = &self.key_file {
+            hasher.update(key_file_key(key_file)?);
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+}
+
+// Open a KDBX 4 file and get entries which have OTP attributes.
+//
+// Entries in the recycle bin and histories of entries are ignored.
+pub fn open(data: &[u8], key: &Key) -> Result<Vec<Entry>, String> {
+    let mut reader = Reader::new(data);
+    if reader.u32()? != SIGNATURE_1 || reader.u32()? != SIGNATURE_2 {
+        return Err(String::from("It is not a KeePass database."));
+    }
+    let _minor = reader.u16()?;
+    if reader.u16()? != MAJOR_VERSION {
+        return Err(String::from("Only KDBX 4 is supported."));
+    }
+
+    let header = Header::read(&mut reader)?;
+    let header_end = reader.position;
+    let header_hash = reader.bytes(32)?;
+    if Sha256::digest(&data[..header_end]).as_slice() != header_hash {
+        return Err(String::from("The database is broken."));
+    }
+
+    let transformed = header.kdf.transform(&key.composite()?)?;
+    let mut seed_key = header.master_seed.clone();
+    seed_key.extend(&transformed);
+    let encryption_key = Sha256::digest(&seed_key);
+    seed_key.push(0x01);
+    let hmac_key = Sha512::digest(&seed_key);
+
+    let header_hmac = reader.bytes(32)?;
+    let mut mac = block_hmac(&hmac_key, u64::MAX);
+    mac.update(&data[..header_end]);
+    if mac.verify_slice(header_hmac).is_err() {
+        return Err(String::from("The password or the key file is incorrect."));
+    }
+
+    let ciphertext = read_blocks(&mut reader, &hmac_key)?;
+    let mut payload = header.cipher.decrypt(&encryption_key, &ciphertext)?;
+    if header.compressed {
+        let mut decompressed = Vec::new();
+        if GzDecoder::new(payload.as_slice())
+            .read_to_end(&mut decompressed)
+            .is_err()
+        {
+            return Err(String::from("The database is broken."));
+        }
+        payload = decompressed;
+    }
+
+    let mut reader = Reader::new(&payload);
+    let mut stream = InnerStream::read(&mut reader)?;
+    let xml = match std::str::from_utf8(&payload[reader.position..]) {
+        Ok(xml) => xml,
+        Err(_) => return Err(String::from("The database is broken.")),
+    };
+
+    read_entries(xml, &mut stream)
+}
+
+// 外側のヘッダー
+struct Header {
+    cipher: Cipher,
+    compressed: bool,
+    master_seed: Vec<u8>,
+    kdf: Kdf,
+}
+
+enum Cipher {
+    Aes256(Vec<u8>),
+    ChaCha20(Vec<u8>),
+}
+
+enum Kdf {
+    Aes {
+        seed: Vec<u8>,
+        rounds: u64,
+    },
+    Argon2 {
+        algorithm: argon2::Algorithm,
+        salt: Vec<u8>,
+        parallelism: u32,
+        // KiB
+        memory: u32,
+        iterations: u32,
+        version: u32,
+    },
+}
+
+impl Header {
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        let mut fields = HashMap::new();
+        loop {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let data = reader.bytes(size)?;
+            if id == HEADER_END {
+                break;
+            }
+            fields.insert(id, data);
+        }
+
+        let field = |id: u8| match fields.get(&id) {
+            Some(data) => Ok(data.to_vec()),
+            None => Err(String::from("The header of the database is broken.")),
+        };
+
+        let iv = field(HEADER_ENCRYPTION_IV)?;
+        let cipher = match field(HEADER_CIPHER_ID)?.as_slice() {
+            id if id == CIPHER_AES256 => Cipher::Aes256(iv),
+            id if id == CIPHER_CHACHA20 => Cipher::ChaCha20(iv),
+            _ => return Err(String::from("Cipher must be AES-256 or ChaCha20.")),
+        };
+        let compressed = match field(HEADER_COMPRESSION)?.as_slice() {
+            [0, 0, 0, 0] => false,
+            [1, 0, 0, 0] => true,
+            _ => return Err(String::from("Unsupported compression.")),
+        };
+
+        Ok(Self {
+            cipher,
+            compressed,
+            master_seed: field(HEADER_MASTER_SEED)?,
+            kdf: Kdf::read(&field(HEADER_KDF_PARAMETERS)?)?,
+        })
+    }
+}
+
+impl Cipher {
+    fn decrypt(&self, key: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Aes256(iv) => {
+                let decryptor = match cbc::Decryptor::<aes::Aes256>::new_from_slices(key, iv) {
+                    Ok(decryptor) => decryptor,
+                    Err(_) => return Err(String::from("The header of the database is broken.")),
+                };
+                match decryptor.decrypt_padded_vec_mut::<Pkcs7>(ciphertext) {
+                    Ok(plaintext) => Ok(plaintext),
+                    Err(_) => Err(String::from("The database is broken.")),
+                }
+            }
+            Self::ChaCha20(iv) => {
+                let mut cipher = match chacha20::ChaCha20::new_from_slices(key, iv) {
+                    Ok(cipher) => cipher,
+                    Err(_) => return Err(String::from("The header of the database is broken.")),
+                };
+                let mut plaintext = ciphertext.to_vec();
+                cipher.apply_keystream(&mut plaintext);
+                Ok(plaintext)
+            }
+        }
+    }
+}
+
+impl Kdf {
+    // Read KDF parameters in VariantDictionary.
+    fn read(data: &[u8]) -> Result<Self, String> {
+        let parameters = read_variant_dictionary(data)?;
+        let parameter = |key: &str| match parameters.get(key) {
+            Some(value) => Ok(value.as_slice()),
+            None => Err(format!("KDF parameter {} is missing.", key)),
+        };
+        let number = |key: &str| {
+            let value = parameter(key)?;
+            let mut bytes = [0u8; 8];
+            match value.len() {
+                4 | 8 => bytes[..value.len()].copy_from_slice(value),
+                _ => return Err(format!("KDF parameter {} is broken.", key)),
+            }
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let bounded = |key: &str, max: u64| {
+            let value = number(key)?;
+            if value > max {
+                return Err(format!("KDF parameter {} is too large.", key));
+            }
+            Ok(value)
+        };
+        let number_u32 = |key: &str, value: u64| match u32::try_from(value) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(format!("KDF parameter {} is broken.", key)),
+        };
+
+        let algorithm = match parameter("$UUID")? {
+            uuid if uuid == KDF_AES => {
+                return Ok(Self::Aes {
+                    seed: parameter("S")?.to_vec(),
+                    rounds: bounded("R", AES_KDF_MAX_ROUNDS)?,
+                })
+            }
+            uuid if uuid == KDF_ARGON2D => argon2::Algorithm::Argon2d,
+            uuid if uuid == KDF_ARGON2ID => argon2::Algorithm::Argon2id,
+            _ => return Err(String::from("KDF must be Argon2 or AES-KDF.")),
+        };
+
+        Ok(Self::Argon2 {
+            algorithm,
+            salt: parameter("S")?.to_vec(),
+            parallelism: number_u32("P", number("P")?)?,
+            memory: number_u32("M", bounded("M", ARGON2_MAX_MEMORY)? / 1024)?,
+            iterations: number_u32("I", bounded("I", ARGON2_MAX_ITERATIONS)?)?,
+            version: number_u32("V", number("V")?)?,
+        })
+    }
+
+    // Transform the composite key.
+    fn transform(&self, composite: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Aes { seed, rounds } => {
+                let cipher = match aes::Aes256::new_from_slice(seed) {
+                    Ok(cipher) => cipher,
+                    Err(_) => return Err(String::from("KDF parameter S is broken.")),
+                };
+                let mut key = composite.to_vec();
+                for _ in 0..*rounds {
+                    for block in key.chunks_mut(16) {
+                        cipher.encrypt_block(block.into());
+                    }
+                }
+                Ok(Sha256::digest(&key).to_vec())
+            }
+            Self::Argon2 {
+                algorithm,
+                salt,
+                parallelism,
+                memory,
+                iterations,
+                version,
+            } => {
+                let version = match argon2::Version::try_from(*version) {
+                    Ok(version) => version,
+                    Err(_) => return Err(String::from("Unsupported Argon2 version.")),
+                };
+                let params = match argon2::Params::new(*memory, *iterations, *parallelism, Some(32))
+                {
+                    Ok(params) => params,
+                    Err(err) => return Err(err.to_string()),
+                };
+
+                let mut key = vec![0u8; 32];
+                let argon2 = argon2::Argon2::new(*algorithm, version, params);
+                match argon2.hash_password_into(composite, salt, &mut key) {
+                    Ok(_) => Ok(key),
+                    Err(err) => Err(err.to_string()),
+                }
+            }
+        }
+    }
+}
+
+// ChaCha20 stream to decrypt protected values in XML
+struct InnerStream {
+    cipher: chacha20::ChaCha20,
+}
+
+impl InnerStream {
+    // Read the inner header.
+    fn read(reader: &mut Reader) -> Result<Self, String> {
+        let mut stream_id = None;
+        let mut stream_key = None;
+        loop {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let data = reader.bytes(size)?;
+            match id {
+                INNER_HEADER_END => break,
+                INNER_HEADER_STREAM_ID if size == 4 => {
+                    stream_id = Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+                }
+                INNER_HEADER_STREAM_KEY => stream_key = Some(data.to_vec()),
+                _ => {}
+            }
+        }
+
+        let key = match (stream_id, stream_key) {
+            (Some(INNER_STREAM_CHACHA20), Some(key)) => Sha512::digest(key),
+            _ => {
+                return Err(String::from(
+                    "Protected values must be encrypted with ChaCha20.",
+                ))
+            }
+        };
+        match chacha20::ChaCha20::new_from_slices(&key[..32], &key[32..44]) {
+            Ok(cipher) => Ok(Self { cipher }),
+            Err(_) => Err(String::from("The database is broken.")),
+        }
+    }
+
+    fn decrypt(&mut self, value: &str) -> Result<String, String> {
+        let mut bytes = match BASE64.decode(value) {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(String::from("The database is broken.")),
+        };
+        self.cipher.apply_keystream(&mut bytes);
+
+        match String::from_utf8(bytes) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(String::from("The database is broken.")),
+        }
+    }
+}
+
+// Read entries from XML.
+//
+// Protected values must be decrypted in document order because they share one stream.
+fn read_entries(xml: &str, stream: &mut InnerStream) -> Result<Vec<Entry>, String> {
+    let document = match roxmltree::Document::parse(xml) {
+        Ok(document) => document,
+        Err(err) => return Err(format!("The database is broken: {}", err)),
+    };
+
+    let mut values = HashMap::new();
+    for node in document
+        .descendants()
+        .filter(|node| node.has_tag_name("Value"))
+    {
+        let text = node.text().unwrap_or_default();
+        let value = if node.attribute("Protected") == Some("True") {
+            stream.decrypt(text)?
+        } else {
+            text.to_string()
+        };
+        values.insert(node.id(), value);
+    }
+
+    let recycle_bin = document
+        .descendants()
+        .find(|node| node.has_tag_name("RecycleBinUUID"))
+        .and_then(|node| node.text());
+    let in_recycle_bin = |node: roxmltree::Node| {
+        node.ancestors()
+            .filter(|ancestor| ancestor.has_tag_name("Group"))
+            .filter_map(|group| child_text(group, "UUID"))
+            .any(|uuid| Some(uuid) == recycle_bin)
+    };
+    let in_history = |node: roxmltree::Node| {
+        node.ancestors()
+            .any(|ancestor| ancestor.has_tag_name("History"))
+    };
+
+    let mut entries = Vec::new();
+    for node in document
+        .descendants()
+        .filter(|node| node.has_tag_name("Entry"))
+    {
+        if in_history(node) || in_recycle_bin(node) {
+            continue;
+        }
+
+        let mut entry: Entry = Default::default();
+        for string in node.children().filter(|child| child.has_tag_name("String")) {
+            let key = child_text(string, "Key").unwrap_or_default();
+            let value = match string.children().find(|child| child.has_tag_name("Value")) {
+                Some(value) => values.get(&value.id()).cloned().unwrap_or_default(),
+                None => continue,
+            };
+
+            match key {
+                "Title" => entry.title = value,
+                "UserName" => entry.username = value,
+                "otp" => entry.otp = Some(value),
+                "TOTP Seed" => entry.totp_seed = Some(value),
+                "TOTP Settings" => entry.totp_settings = Some(value),
+                _ => {}
+            }
+        }
+
+        if entry.otp.is_some() || entry.totp_seed.is_some() {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+fn child_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(|child| child.text())
+}
+
+// Read and verify the HMAC block stream.
+fn read_blocks(reader: &mut Reader, hmac_key: &[u8]) -> Result<Vec<u8>, String> {
+    let mut ciphertext = Vec::new();
+
+    for index in 0u64.. {
+        let expected = reader.bytes(32)?;
+        let size = reader.u32()?;
+        let data = reader.bytes(size as usize)?;
+
+        let mut mac = block_hmac(hmac_key, index);
+        mac.update(&index.to_le_bytes());
+        mac.update(&size.to_le_bytes());
+        mac.update(data);
+        if mac.verify_slice(expected).is_err() {
+            return Err(String::from("The database is broken."));
+        }
+
+        if size == 0 {
+            break;
+        }
+        ciphertext.extend(data);
+    }
+
+    Ok(ciphertext)
+}
+
+// HMAC of a block with a key for the block index
+fn block_hmac(hmac_key: &[u8], index: u64) -> HmacSha256 {
+    let mut hasher = Sha512::new();
+    hasher.update(index.to_le_bytes());
+    hasher.update(hmac_key);
+
+    <HmacSha256 as Mac>::new_from_slice(&hasher.finalize()).unwrap()
+}
+
+// Read VariantDictionary. Values are kept as bytes.
+fn read_variant_dictionary(data: &[u8]) -> Result<HashMap<String, Vec<u8>>, String> {
+    let mut reader = Reader::new(data);
+    if reader.u16()? >> 8 != 1 {
+        return Err(String::from("Unsupported KDF parameters."));
+    }
+
+    let mut dictionary = HashMap::new();
+    loop {
+        if reader.u8()? == 0 {
+            break;
+        }
+        let key_size = reader.u32()? as usize;
+        let key = String::from_utf8_lossy(reader.bytes(key_size)?).to_string();
+        let value_size = reader.u32()? as usize;
+        dictionary.insert(key, reader.bytes(value_size)?.to_vec());
+    }
+
+    Ok(dictionary)
+}
+
+// Get the key from a key file.
+//
+// XML key files (version 1.0 and 2.0), 32 bytes binary and 64 hex characters are supported.
+// Other files are hashed with SHA-256.
+fn key_file_key(data: &[u8]) -> Result<Vec<u8>, String> {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Ok(document) = roxmltree::Document::parse(text) {
+            if document.root_element().has_tag_name("KeyFile") {
+                return xml_key_file_key(&document);
+            }
+        }
+
+        let text = text.trim();
+        if text.len() == 64 {
+            if let Ok(key) = hex::decode(text) {
+                return Ok(key);
+            }
+        }
+    }
+
+    if data.len() == 32 {
+        return Ok(data.to_vec());
+    }
+    Ok(Sha256::digest(data).to_vec())
+}
+
+fn xml_key_file_key(document: &roxmltree::Document) -> Result<Vec<u8>, String> {
+    let text = |name: &str| {
+        document
+            .descendants()
+            .find(|node| node.has_tag_name(name))
+            .and_then(|node| node.text())
+            .map(|text| text.split_whitespace().collect::<String>())
+    };
+
+    let key = match (text("Version").as_deref(), text("Data")) {
+        (Some("2.0"), Some(data)) => hex::decode(data).ok(),
+        (Some("1.0"), Some(data)) => BASE64.decode(data).ok(),
+        _ => None,
+    };
+    match key {
+        Some(key) => Ok(key),
+        None => Err(String::from("The key file is broken.")),
+    }
+}
+
+impl Entry {
+    fn name(&self) -> String {
+        import::profile_name(&self.title, &self.username)
+    }
+
+    // Build a profile from the legacy attributes of KeePassXC.
+    fn legacy_profile(&self, seed: &str) -> Result<Profile, ValidationError> {
+        let mut profile = Profile::new("", &otpauth::normalize_secret(seed)?);
+
+        let settings = self.totp_settings.as_deref().unwrap_or_default();
+        if let Some((period, digits)) = settings.split_once(';') {
+            match period.trim().parse::<u64>() {
+                Ok(period) => profile.set_period(period),
+                Err(_) => return Err(ValidationError::InvalidFormat("TOTP Settings is broken.")),
+            }
+            match digits.trim() {
+                STEAM_SETTINGS => profile.set_type(ProfileType::Steam),
+                digits => match digits.parse::<u8>() {
+                    Ok(digits) => profile.set_digits(digits),
+                    Err(_) => {
+                        return Err(ValidationError::InvalidFormat("TOTP Settings is broken."))
+                    }
+                },
+            }
+        }
+
+        Ok(profile)
+    }
+}
+
+impl import::Entry for Entry {
+    // A label to show the entry without the secret. "Title:UserName" or "Title"
+    fn label(&self) -> String {
+        if self.username.is_empty() {
+            return self.title.clone();
+        }
+
+        format!("{}:{}", self.title, self.username)
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the title and the username.
+    // They are also the issuer and the account unless the URI has them.
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        let mut profile = match (&self.otp, &self.totp_seed) {
+            (Some(otp), _) if otp.trim().starts_with("otpauth://") => {
+                KeyUri::parse(otp.trim())?.to_profile()
+            }
+            (Some(otp), _) => keeotp_profile(otp)?,
+            (None, Some(seed)) => self.legacy_profile(seed)?,
+            (None, None) => return Err(ValidationError::Requires("OTP must be present.")),
+        };
+
+        profile.set_name(&self.name());
+        if profile.get_issuer().is_none() {
+            profile.set_issuer(&self.title);
+        }
+        if profile.get_account().is_none() {
+            profile.set_account(&self.username);
+        }
+        Ok(profile)
+    }
+}
+
+// Build a profile from the KeeOtp format. "key=SEED&step=30&size=6&otpHashMode=Sha1"
+fn keeotp_profile(otp: &str) -> Result<Profile, ValidationError> {
+    let mut profile = Profile::new("", "");
+
+    for (key, value) in otp
+        .trim()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+    {
+        match key {
+            "key" => profile = Profile::new("", &otpauth::normalize_secret(value)?),
+            "step" => match value.parse::<u64>() {
+                Ok(period) => profile.set_period(period),
+                Err(_) => return Err(ValidationError::InvalidFormat("Step must be a number.")),
+            },
+            "size" => match value.parse::<u8>() {
+                Ok(digits) => profile.set_digits(digits),
+                Err(_) => return Err(ValidationError::InvalidFormat("Size must be a number.")),
+            },
+            "otpHashMode" => match value.parse::<Algorithm>() {
+                Ok(algorithm) => profile.set_algorithm(algorithm),
+                Err(_) => {
+                    return Err(ValidationError::Unsupported(
+                        "Algorithm must be SHA1, SHA256 or SHA512.",
+                    ))
+                }
+            },
+            "type" if !value.eq_ignore_ascii_case("totp") => {
+                return Err(ValidationError::Unsupported("Type must be TOTP."))
+            }
+            _ => {}
+        }
+    }
+
+    Ok(profile)
+}
+
+// Little endian のバイト列を読む
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    fn bytes(&mut self, size: usize) -> Result<&'a [u8], String> {
+        match self.bytes.get(self.position..self.position + size) {
+            Some(bytes) => {
+                self.position += size;
+                Ok(bytes)
+            }
+            None => Err(String::from("The database is broken.")),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    // Argon2id, ChaCha20 and gzip. The password is "test".
+    const ARGON2_CHACHA20: &[u8] = include_bytes!("../tests/fixtures/kdbx-argon2-chacha20.kdbx");
+    // AES-KDF and AES-256 without compression. Only the key file is required.
+    const AES_KEY_FILE: &[u8] = include_bytes!("../tests/fixtures/kdbx-aes-keyfile.kdbx");
+    const KEY_FILE: &[u8] = include_bytes!("../tests/fixtures/kdbx.keyx");
+
+    fn expected_profiles() -> Vec<Result<Profile, ValidationError>> {
+        let mut uri = Profile::new("Example-alice", "JBSWY3DPEHPK3PXP");
+        uri.set_issuer("Example");
+        uri.set_account("alice");
+        uri.set_algorithm(Algorithm::Sha256);
+        uri.set_digits(8);
+        let mut legacy = Profile::new("Legacy-bob", "GEZDGNBVGY3TQOJQ");
+        legacy.set_issuer("Legacy");
+        legacy.set_account("bob");
+        legacy.set_period(60);
+        legacy.set_digits(8);
+        let mut steam = Profile::new("Steam-carol", "JBSWY3DPEHPK3PXP");
+        steam.set_issuer("Steam");
+        steam.set_account("carol");
+        steam.set_type(ProfileType::Steam);
+        let mut keeotp = Profile::new("KeeOtp-dave", "JBSWY3DPEHPK3PXP");
+        keeotp.set_issuer("KeeOtp");
+        keeotp.set_account("dave");
+        keeotp.set_period(60);
+        keeotp.set_digits(8);
+        keeotp.set_algorithm(Algorithm::Sha512);
+
+        vec![
+            Ok(uri),
+            Ok(legacy),
+            Ok(steam),
+            Ok(keeotp),
+            Err(ValidationError::InvalidFormat(
+                "Secret must be encoded in base32.",
+            )),
+        ]
+    }
+
+    // KDF のパラメーターの VariantDictionary を作る
+    fn kdf_parameters(uuid: &[u8], numbers: &[(&str, u64)]) -> Vec<u8> {
+        fn push(data: &mut Vec<u8>, value_type: u8, key: &str, value: &[u8]) {
+            data.push(value_type);
+            data.extend((key.len() as u32).to_le_bytes());
+            data.extend(key.as_bytes());
+            data.extend((value.len() as u32).to_le_bytes());
+            data.extend(value);
+        }
+
+        let mut data = vec![0x00, 0x01];
+        push(&mut data, 0x42, "$UUID", uuid);
+        push(&mut data, 0x42, "S", &[0u8; 32]);
+        for (key, value) in numbers {
+            push(&mut data, 0x05, key, &value.to_le_bytes());
+        }
+        data.push(0);
+        data
+    }
+
+    #[test]
+    fn aes_kdf_with_too_many_rounds() {
+        assert!(Kdf::read(&kdf_parameters(&KDF_AES, &[("R", 60_000)])).is_ok());
+        assert_eq!(
+            Kdf::read(&kdf_parameters(&KDF_AES, &[("R", 1 << 40)])).err(),
+            Some(String::from("KDF parameter R is too large."))
+        );
+    }
+
+    #[test]
+    fn argon2_kdf_with_too_large_params() {
+        let argon2 = |p: u64, m: u64, i: u64| {
+            Kdf::read(&kdf_parameters(
+                &KDF_ARGON2ID,
+                &[("P", p), ("M", m), ("I", i), ("V", 0x13)],
+            ))
+            .err()
+        };
+
+        assert_eq!(argon2(2, 64 << 20, 10), None);
+        assert_eq!(
+            argon2(2, 1 << 40, 10),
+            Some(String::from("KDF parameter M is too large."))
+        );
+        assert_eq!(
+            argon2(2, 64 << 20, 1 << 33),
+            Some(String::from("KDF parameter I is too large."))
+        );
+        assert_eq!(
+            argon2(1 << 33, 64 << 20, 10),
+            Some(String::from("KDF parameter P is broken."))
+        );
+    }
+
+    #[test]
+    fn open_with_password() {
+        let entries = open(ARGON2_CHACHA20, &Key::new(Some("test"), None)).unwrap();
+        let profiles: Vec<Result<Profile, ValidationError>> =
+            entries.iter().map(|entry| entry.to_profile()).collect();
+
+        assert_eq!(profiles, expected_profiles());
+    }
+
+    #[test]
+    fn open_with_key_file() {
+        let entries = open(AES_KEY_FILE, &Key::new(None, Some(KEY_FILE.to_vec()))).unwrap();
+        let profiles: Vec<Result<Profile, ValidationError>> =
+            entries.iter().map(|entry| entry.to_profile()).collect();
+
+        assert_eq!(profiles, expected_profiles());
+    }
+
+    #[test]
+    fn open_with_wrong_key() {
+        assert_eq!(
+            open(ARGON2_CHACHA20, &Key::new(Some("wrong"), None)).unwrap_err(),
+            "The password or the key file is incorrect."
+        );
+        assert_eq!(
+            open(
+                AES_KEY_FILE,
+                &Key::new(Some("test"), Some(KEY_FILE.to_vec()))
+            )
+            .unwrap_err(),
+            "The password or the key file is incorrect."
+        );
+        assert!(open(ARGON2_CHACHA20, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn open_not_kdbx() {
+        assert_eq!(
+            open(
+                b"otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP",
+                &Default::default()
+            )
+            .unwrap_err(),
+            "It is not a KeePass database."
+        );
+    }
+
+    #[test]
+    fn keeotp_with_invalid_key() {
+        assert_eq!(
+            keeotp_profile("key=not*base32!&step=30"),
+            Err(ValidationError::InvalidFormat(
+                "Secret must be encoded in base32."
+            ))
+        );
+        assert_eq!(
+            keeotp_profile("key=jbsw y3dp ehpk 3pxp").unwrap(),
+            Profile::new("", "JBSWY3DPEHPK3PXP")
+        );
+    }
+
+    #[test]
+    fn key_file_formats() {
+        let key: Vec<u8> = (0..32).collect();
+
+        assert_eq!(key_file_key(KEY_FILE).unwrap(), key);
+        assert_eq!(key_file_key(&key).unwrap(), key);
+        assert_eq!(key_file_key(hex::encode(&key).as_bytes()).unwrap(), key);
+        assert_eq!(
+            key_file_key(b"any file").unwrap(),
+            Sha256::digest(b"any file").to_vec()
+        );
+    }
+}
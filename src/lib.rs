@@ -1,5 +1,28 @@
-mod config;
+pub mod aegis;
+pub mod age_vault;
+#[cfg(unix)]
+pub mod agent;
+pub mod algorithm;
+pub mod andotp;
+pub mod bitwarden;
+pub mod config;
+mod crypto;
+pub mod csv_import;
+pub mod datetime;
+pub mod encryption;
+pub mod filter;
+pub mod fuzzy;
 mod hmac_sha1;
+mod hmac_sha2;
 mod hotp;
+pub mod import;
+pub mod kdbx;
 pub mod mfa;
+pub mod migration;
+pub mod otpauth;
+pub mod pass;
+pub mod qr;
+pub mod schema;
+mod storage;
 pub mod totp;
+pub mod twofas;
@@ -1,10 +1,33 @@
 extern crate clap;
 extern crate mfa_cli;
+extern crate rpassword;
 
-use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand};
-use mfa_cli::mfa::Mfa;
+use clap::{ArgAction, ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use mfa_cli::aegis;
+#[cfg(unix)]
+use mfa_cli::agent;
+use mfa_cli::algorithm::Algorithm;
+use mfa_cli::andotp;
+use mfa_cli::bitwarden;
+use mfa_cli::config;
+use mfa_cli::config::ProfileType;
+use mfa_cli::csv_import;
+use mfa_cli::filter::Filter;
+use mfa_cli::import;
+use mfa_cli::kdbx;
+use mfa_cli::mfa::{self, Mfa};
+use mfa_cli::migration;
+use mfa_cli::otpauth::{self, KeyUri};
+use mfa_cli::pass;
+use mfa_cli::qr;
 use mfa_cli::totp;
+use mfa_cli::twofas;
+use std::collections::BTreeMap;
+use std::fs;
 use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process;
 use std::{thread, time};
 
@@ -32,6 +55,87 @@ enum Commands {
     Profile(Profile),
     /// Show MFA code for the profile.
     Show(Show),
+    #[clap(subcommand)]
+    #[clap(
+        long_about = "You will encrypt the profile file with a passphrase or to age recipients. The passphrase is asked on the terminal, or read from the file descriptor in MFA_CLI_PASSPHRASE_FD or MFA_CLI_PASSPHRASE. The age identity is read from MFA_CLI_IDENTITY or ~/.ssh/id_ed25519. The profile file which other users can read is refused; set MFA_CLI_PERMISSIONS=warn to only warn."
+    )]
+    /// You will encrypt or decrypt the profile file.
+    Vault(Vault),
+    #[clap(subcommand)]
+    #[clap(
+        long_about = "You will run an agent which keeps the unlocked profiles in memory like ssh-agent. Run `eval \"$(mfa-cli agent start)\"` and `show` asks the agent in MFA_CLI_AGENT_SOCK for codes without the passphrase."
+    )]
+    /// You will run an agent which keeps the unlocked profiles.
+    #[cfg(unix)]
+    Agent(Agent),
+}
+
+#[cfg(unix)]
+#[derive(Subcommand)]
+enum Agent {
+    /// Start the agent in the background, unlock it and print the env variable to use it.
+    Start(AgentServe),
+    /// Run the agent in the foreground.
+    Serve(AgentServe),
+    /// Make the agent forget the profiles.
+    Lock,
+    /// Make the agent read the profile file.
+    Unlock,
+    /// Stop the agent.
+    Stop,
+}
+
+#[cfg(unix)]
+#[derive(Args)]
+struct AgentServe {
+    #[clap(long, value_parser)]
+    /// Path of the socket. A new directory only for the user is created in the temporary directory by default.
+    socket: Option<PathBuf>,
+    #[clap(long, value_parser, default_value_t = 900)]
+    /// Seconds to keep the profiles after the last use. 0 keeps them until the agent is locked.
+    timeout: u64,
+}
+
+#[derive(Subcommand)]
+enum Vault {
+    /// Encrypt the profile file with a new passphrase or to age recipients.
+    Encrypt(VaultEncrypt),
+    /// Decrypt the profile file and save it in plaintext.
+    Decrypt,
+    /// Change the passphrase of the encrypted profile file.
+    Passphrase,
+    #[clap(subcommand)]
+    /// Manage recipients who can open the profile file.
+    Recipients(Recipients),
+    /// Make the config directory and the profile file readable only by you.
+    FixPermissions,
+}
+
+#[derive(Args)]
+struct VaultEncrypt {
+    #[clap(short, long, value_parser)]
+    /// Encrypt to an age X25519 (age1...) or ssh-ed25519 public key instead of a passphrase.
+    recipient: Vec<String>,
+    #[clap(short = 'R', long, value_parser)]
+    /// Encrypt to public keys in a file, one per line.
+    recipients_file: Vec<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Recipients {
+    /// Show recipients.
+    List,
+    /// Add recipients and encrypt the profile file again.
+    Add(RecipientKeys),
+    /// Remove recipients and encrypt the profile file again.
+    Remove(RecipientKeys),
+}
+
+#[derive(Args)]
+struct RecipientKeys {
+    #[clap(value_parser, required = true)]
+    /// Enter age X25519 (age1...) or ssh-ed25519 public keys.
+    recipients: Vec<String>,
 }
 
 #[derive(Subcommand)]
@@ -39,9 +143,21 @@ enum Profile {
     /// Add a new profile
     Add(Add),
     /// Show registered profile list.
-    List,
+    List(List),
     /// Remove any profile
     Remove(Remove),
+    /// Edit the secret key, the code parameters, the issuer, the account, notes and tags of a profile.
+    Edit(Edit),
+    /// Rename a profile.
+    Rename(Rename),
+    /// Show or set the counter of a HOTP profile.
+    Counter(Counter),
+    /// Export profiles with their secret keys.
+    Export(Export),
+    /// Show a QR code to register the profile to another authenticator.
+    Qr(Qr),
+    /// Import profiles from other authenticators.
+    Import(Box<Import>),
 }
 
 #[derive(Args)]
@@ -52,16 +168,237 @@ struct Show {
     #[clap(short, long, action = ArgAction::SetTrue)]
     /// After showing code, watch for changes.
     watch: bool,
+    #[clap(long, action = ArgAction::SetTrue)]
+    /// Require the exact profile name. Otherwise a unique prefix or fuzzy match is accepted.
+    exact: bool,
+    #[clap(flatten)]
+    filters: Filters,
 }
 
 #[derive(Args)]
 struct Add {
-    #[clap(value_parser)]
+    #[clap(value_parser, required_unless_present = "uri")]
     /// Enter a profile name as a label to manage your secret key.
-    account_name: String,
-    #[clap(value_parser)]
+    /// With --uri, it is derived from the URI if omitted.
+    account_name: Option<String>,
+    #[clap(value_parser, required_unless_present = "uri")]
     /// Enter the secret key that be provided by AWS IAM.
-    key: String,
+    key: Option<String>,
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = ["key", "profile_type", "algorithm", "digits", "period", "t0", "counter"]
+    )]
+    /// Register a profile from an otpauth:// URI instead of KEY.
+    uri: Option<String>,
+    #[clap(long = "type", value_parser, default_value_t = ProfileType::Totp)]
+    /// Type of the MFA code (totp, hotp or steam).
+    profile_type: ProfileType,
+    #[clap(long, value_parser, default_value_t = Algorithm::Sha1)]
+    /// Hash algorithm of HMAC (SHA1, SHA256 or SHA512).
+    algorithm: Algorithm,
+    #[clap(long, value_parser, default_value_t = totp::TOTP_DIGITS)]
+    /// Number of digits of the MFA code.
+    digits: u8,
+    #[clap(long, value_parser, default_value_t = totp::TIME_STEP)]
+    /// Seconds that a MFA code is valid for.
+    period: u64,
+    #[clap(long, value_parser, default_value_t = 0)]
+    /// Unix time to start counting time steps.
+    t0: u64,
+    #[clap(long, value_parser, default_value_t = 0)]
+    /// Initial counter value for a HOTP profile.
+    counter: u64,
+    #[clap(flatten)]
+    metadata: Metadata,
+}
+
+// プロファイルの説明。URI から取れる issuer と account は上書きする
+#[derive(Args)]
+struct Metadata {
+    #[clap(long, value_parser)]
+    /// The service which issued the secret key. e.g. GitHub. An empty value removes it.
+    issuer: Option<String>,
+    #[clap(long, value_parser)]
+    /// The account in the service. e.g. alice@example.com. An empty value removes it.
+    account: Option<String>,
+    #[clap(long, value_parser)]
+    /// Free-form notes. An empty value removes them.
+    notes: Option<String>,
+    #[clap(long = "tag", value_parser)]
+    /// Add a tag. It can be given several times.
+    tags: Vec<String>,
+}
+
+#[derive(Args)]
+struct Edit {
+    #[clap(value_parser)]
+    /// Enter a profile name that you want to edit.
+    profile: String,
+    #[clap(long, value_parser)]
+    /// A new secret key.
+    secret: Option<String>,
+    #[clap(long, value_parser)]
+    /// Hash algorithm of HMAC (SHA1, SHA256 or SHA512).
+    algorithm: Option<Algorithm>,
+    #[clap(long, value_parser)]
+    /// Number of digits of the MFA code.
+    digits: Option<u8>,
+    #[clap(long, value_parser)]
+    /// Seconds that a MFA code is valid for.
+    period: Option<u64>,
+    #[clap(flatten)]
+    metadata: Metadata,
+    #[clap(long = "untag", value_parser)]
+    /// Remove a tag. It can be given several times.
+    untags: Vec<String>,
+}
+
+#[derive(Args)]
+struct Rename {
+    #[clap(value_parser)]
+    /// Enter a profile name that you want to rename.
+    profile: String,
+    #[clap(value_parser)]
+    /// Enter a new profile name.
+    new_name: String,
+}
+
+#[derive(Args)]
+struct List {
+    #[clap(short, long, action = ArgAction::SetTrue)]
+    /// Show the type, the issuer, the account, tags, times and notes of each profile.
+    long: bool,
+    #[clap(flatten)]
+    filters: Filters,
+}
+
+// プロファイルを絞り込む条件
+#[derive(Args)]
+struct Filters {
+    #[clap(long = "tag", value_parser)]
+    /// Choose profiles which have the tag. Profiles must have all of the tags if it is given several times.
+    tags: Vec<String>,
+    #[clap(long, value_parser)]
+    /// Choose profiles whose names match the glob (* and ?). e.g. 'github-*'
+    glob: Option<String>,
+    #[clap(long, value_parser = parse_regex)]
+    /// Choose profiles whose names match the regex.
+    regex: Option<String>,
+}
+
+impl Filters {
+    fn to_filter(&self) -> Filter {
+        Filter::new(&self.tags, self.glob.as_deref(), self.regex.as_deref())
+    }
+}
+
+fn parse_regex(value: &str) -> Result<String, String> {
+    match regex::Regex::new(value) {
+        Ok(_) => Ok(value.to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[derive(Args)]
+struct Counter {
+    #[clap(value_parser)]
+    /// Enter a HOTP profile name.
+    profile: String,
+    #[clap(value_parser)]
+    /// Enter a new counter value. If it is omitted, show the current counter.
+    counter: Option<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ExportFormat {
+    /// otpauth:// URI per line
+    Uri,
+    /// otpauth-migration:// URI per line to import into Google Authenticator
+    Migration,
+    /// Aegis vault JSON
+    Aegis,
+}
+
+#[derive(Args)]
+struct Export {
+    #[clap(long, value_enum, default_value_t = ExportFormat::Uri)]
+    /// Output format.
+    format: ExportFormat,
+    #[clap(long, value_parser, default_value_t = 10)]
+    /// Number of profiles in a migration URI.
+    batch_size: usize,
+    #[clap(long, action = ArgAction::SetTrue)]
+    /// Show migration URIs as QR codes.
+    qr: bool,
+    #[clap(long, action = ArgAction::SetTrue)]
+    /// Encrypt the Aegis vault with a password.
+    encrypt: bool,
+    #[clap(short, long, action = ArgAction::SetTrue)]
+    /// Skip the confirmation to reveal secret keys.
+    yes: bool,
+    #[clap(value_parser)]
+    /// Enter profile names that you want to export. If they are omitted, export all of profiles.
+    profiles: Vec<String>,
+    #[clap(flatten)]
+    filters: Filters,
+}
+
+#[derive(Args)]
+struct Qr {
+    #[clap(value_parser)]
+    /// Enter a profile name that you want to show as a QR code.
+    profile: String,
+    #[clap(short, long, value_parser)]
+    /// Write the QR code to a PNG or SVG file instead of the terminal.
+    output: Option<PathBuf>,
+    #[clap(short, long, action = ArgAction::SetTrue)]
+    /// Skip the confirmation to reveal the secret key.
+    yes: bool,
+}
+
+#[derive(Args)]
+#[clap(group(ArgGroup::new("source").required(true).multiple(true).args(["qr", "uri", "aegis", "andotp", "twofas", "bitwarden", "csv", "kdbx", "pass"])))]
+struct Import {
+    #[clap(long, value_parser)]
+    /// Enter PNG or JPEG image files which contain QR codes.
+    qr: Vec<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Enter otpauth:// or otpauth-migration:// (Google Authenticator export) URIs.
+    uri: Vec<String>,
+    #[clap(long, value_parser)]
+    /// Enter Aegis export files (plain or encrypted JSON). The password is asked if encrypted.
+    aegis: Vec<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Enter andOTP backup files (plain JSON or encrypted .json.aes). The password is asked if encrypted.
+    andotp: Vec<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Enter 2FAS backup files (.2fas). The password is asked if protected.
+    twofas: Vec<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Enter Bitwarden export files (unencrypted JSON). Items which have a TOTP seed are imported.
+    bitwarden: Vec<PathBuf>,
+    #[clap(long, value_parser)]
+    /// Enter CSV files which have a header row.
+    csv: Vec<PathBuf>,
+    #[clap(long, value_parser, value_name = "FIELD=COLUMN,...")]
+    /// Map CSV columns (header names or 1-based indexes) to name, secret, issuer, digits and period. [default: name=name,secret=secret]
+    csv_columns: Option<csv_import::Columns>,
+    #[clap(long, value_parser)]
+    /// Enter KeePass databases (KDBX 4). Entries which have TOTP attributes are imported.
+    kdbx: Vec<PathBuf>,
+    #[clap(long, value_parser, value_name = "PATH")]
+    /// Enter the key file of the KeePass databases.
+    kdbx_key_file: Option<PathBuf>,
+    #[clap(long, action = ArgAction::SetTrue, requires = "kdbx_key_file")]
+    /// Open the KeePass databases only with the key file without asking the password.
+    kdbx_no_password: bool,
+    #[clap(long, value_parser, value_name = "STORE", num_args = 0..=1)]
+    /// Import otpauth URIs of pass-otp from a password-store. Entries are decrypted with gpg. [default: $PASSWORD_STORE_DIR or ~/.password-store]
+    pass: Option<Option<PathBuf>>,
+    #[clap(long, action = ArgAction::SetTrue)]
+    /// Show what will be imported without saving.
+    dry_run: bool,
 }
 
 #[derive(Args)]
@@ -72,6 +409,41 @@ struct Remove {
 }
 
 fn main() {
+    // parse arguments first not to ask the passphrase for --help
+    let cli = Cli::parse();
+
+    // the agent commands and the agent don't need the profile file in this process
+    match &cli.command {
+        #[cfg(unix)]
+        Some(Commands::Agent(agent)) => {
+            match agent {
+                Agent::Start(args) => agent_start(args),
+                Agent::Serve(args) => agent_serve(args),
+                Agent::Lock => agent_request(&agent::Request::Lock),
+                Agent::Unlock => {
+                    if let Err(err) = agent_unlock(&agent_socket()) {
+                        eprintln!("failed to unlock the agent: {}", err);
+                        process::exit(10);
+                    }
+                }
+                Agent::Stop => agent_request(&agent::Request::Stop),
+            };
+            process::exit(0);
+        }
+        // the profile file which other users can read is refused by Mfa::new
+        Some(Commands::Vault(Vault::FixPermissions)) => {
+            vault_fix_permissions();
+            process::exit(0);
+        }
+        #[cfg(unix)]
+        Some(Commands::Show(args)) => {
+            if let Some(socket) = agent::socket_from_env() {
+                show_with_agent(&socket, args);
+            }
+        }
+        _ => {}
+    }
+
     let mut mfa = match Mfa::new() {
         Ok(mfa) => mfa,
         Err(err) => {
@@ -80,15 +452,30 @@ fn main() {
         }
     };
 
-    let cli = Cli::parse();
-
     match &cli.command {
         Some(Commands::Profile(profile)) => match profile {
             Profile::Add(args) => profile_add(&mut mfa, args),
-            Profile::List => profile_list(&mfa),
+            Profile::List(args) => profile_list(&mfa, args),
+            Profile::Edit(args) => profile_edit(&mut mfa, args),
+            Profile::Rename(args) => profile_rename(&mut mfa, args),
             Profile::Remove(args) => profile_remove(&mut mfa, args),
+            Profile::Counter(args) => profile_counter(&mut mfa, args),
+            Profile::Export(args) => profile_export(&mfa, args),
+            Profile::Qr(args) => profile_qr(&mfa, args),
+            Profile::Import(args) => profile_import(&mut mfa, args),
+        },
+        Some(Commands::Show(args)) => show(&mut mfa, args),
+        #[cfg(unix)]
+        Some(Commands::Agent(_)) => unreachable!(),
+        Some(Commands::Vault(vault)) => match vault {
+            Vault::Encrypt(args) => vault_encrypt(&mut mfa, args),
+            Vault::Decrypt => vault_decrypt(&mut mfa),
+            Vault::Passphrase => vault_passphrase(&mut mfa),
+            Vault::Recipients(Recipients::List) => vault_recipients_list(&mfa),
+            Vault::Recipients(Recipients::Add(args)) => vault_recipients_add(&mut mfa, args),
+            Vault::Recipients(Recipients::Remove(args)) => vault_recipients_remove(&mut mfa, args),
+            Vault::FixPermissions => unreachable!(),
         },
-        Some(Commands::Show(args)) => show(&mfa, args),
         &None => Cli::command().print_long_help().unwrap(),
     };
 
@@ -96,7 +483,13 @@ fn main() {
 }
 
 fn profile_add(mfa: &mut Mfa, args: &Add) {
-    if let Err(err) = mfa.register_profile(&args.account_name, &args.key) {
+    let mut profile = match &args.uri {
+        Some(uri) => build_profile_from_uri(uri, args.account_name.as_deref()),
+        None => build_profile_from_args(args),
+    };
+    apply_metadata(&mut profile, &args.metadata);
+
+    if let Err(err) = mfa.add_profile(profile) {
         eprintln!("failed to registring profile: {}", err);
         process::exit(3);
     };
@@ -107,15 +500,205 @@ fn profile_add(mfa: &mut Mfa, args: &Add) {
     process::exit(0);
 }
 
-fn profile_list(mfa: &Mfa) {
+fn build_profile_from_args(args: &Add) -> config::Profile {
+    let account_name = args.account_name.as_deref().unwrap_or_default();
+    let key = args.key.as_deref().unwrap_or_default();
+
+    let mut profile = config::Profile::new(account_name, key);
+    profile.set_type(args.profile_type);
+    profile.set_algorithm(args.algorithm);
+    profile.set_digits(args.digits);
+    profile.set_period(args.period);
+    profile.set_t0(args.t0);
+    profile.set_counter(args.counter);
+    profile
+}
+
+// exit process with code 3 if failed to parse the URI.
+fn build_profile_from_uri(uri: &str, account_name: Option<&str>) -> config::Profile {
+    let mut profile = match KeyUri::parse(uri) {
+        Ok(key_uri) => key_uri.to_profile(),
+        Err(err) => {
+            eprintln!("failed to parse the URI: {}", err);
+            process::exit(3);
+        }
+    };
+
+    if let Some(account_name) = account_name {
+        profile.set_name(account_name);
+    }
+    profile
+}
+
+fn apply_metadata(profile: &mut config::Profile, metadata: &Metadata) {
+    if let Some(issuer) = &metadata.issuer {
+        profile.set_issuer(issuer);
+    }
+    if let Some(account) = &metadata.account {
+        profile.set_account(account);
+    }
+    if let Some(notes) = &metadata.notes {
+        profile.set_notes(notes);
+    }
+    for tag in &metadata.tags {
+        profile.add_tag(tag);
+    }
+}
+
+// exit process with code 3 if the profile can't be edited.
+fn profile_edit(mfa: &mut Mfa, args: &Edit) {
+    // 取り込みと同じく空白を除いて大文字にし、base32 でなければ保存しない
+    let secret = match args.secret.as_deref().map(otpauth::normalize_secret) {
+        Some(Ok(secret)) => Some(secret),
+        Some(Err(err)) => {
+            eprintln!("failed to edit the profile: {}", err);
+            process::exit(3);
+        }
+        None => None,
+    };
+
+    let result = mfa.edit_profile(&args.profile, |profile| {
+        if let Some(secret) = &secret {
+            profile.set_secret(secret);
+        }
+        if let Some(algorithm) = args.algorithm {
+            profile.set_algorithm(algorithm);
+        }
+        if let Some(digits) = args.digits {
+            profile.set_digits(digits);
+        }
+        if let Some(period) = args.period {
+            profile.set_period(period);
+        }
+        apply_metadata(profile, &args.metadata);
+        for tag in &args.untags {
+            profile.remove_tag(tag);
+        }
+    });
+    if let Err(err) = result {
+        eprintln!("failed to edit the profile: {}", err);
+        process::exit(3);
+    }
+
+    dump_config(mfa);
+    println!("Edited the profile");
+    process::exit(0);
+}
+
+// exit process with code 3 if the profile can't be renamed.
+fn profile_rename(mfa: &mut Mfa, args: &Rename) {
+    if let Err(err) = mfa.rename_profile(&args.profile, &args.new_name) {
+        eprintln!("failed to rename the profile: {}", err);
+        process::exit(3);
+    }
+
+    dump_config(mfa);
+    println!("Renamed the profile");
+    process::exit(0);
+}
+
+fn profile_list(mfa: &Mfa, args: &List) {
+    // the regex is validated by clap
+    let profiles = mfa
+        .get_profiles_by_filter(&[], &args.filters.to_filter())
+        .unwrap_or_default();
+    if args.long {
+        profile_list_long(&profiles);
+    }
+
     println!();
-    for profile in mfa.list_profiles() {
-        print!(" {}", profile);
+    if profiles.iter().all(|profile| profile.get_tags().is_empty()) {
+        for profile in profiles {
+            print!(" {}", profile.get_name());
+        }
+        println!();
+        process::exit(0);
+    }
+
+    // タグごとにまとめる。タグのないプロファイルは最後
+    let mut groups: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    let mut untagged = Vec::new();
+    for profile in profiles {
+        for tag in profile.get_tags() {
+            groups.entry(tag).or_default().push(profile.get_name());
+        }
+        if profile.get_tags().is_empty() {
+            untagged.push(profile.get_name().as_str());
+        }
+    }
+    for (tag, names) in groups {
+        println!("[{}]", tag);
+        println!(" {}", names.join(" "));
+    }
+    if !untagged.is_empty() {
+        println!("[no tag]");
+        println!(" {}", untagged.join(" "));
     }
     println!();
     process::exit(0);
 }
 
+// Show profiles in a table.
+fn profile_list_long(profiles: &[&config::Profile]) {
+    let datetime = |datetime: Option<&toml::value::Datetime>| match datetime {
+        Some(datetime) => datetime.to_string(),
+        None => String::from("-"),
+    };
+    let text = |text: Option<&str>| text.unwrap_or("-").replace('\n', " ");
+
+    let mut rows = vec![[
+        "NAME",
+        "TYPE",
+        "ISSUER",
+        "ACCOUNT",
+        "TAGS",
+        "CREATED",
+        "LAST USED",
+        "NOTES",
+    ]
+    .map(String::from)];
+    for profile in profiles {
+        let tags = match profile.get_tags().join(",") {
+            tags if tags.is_empty() => String::from("-"),
+            tags => tags,
+        };
+        rows.push([
+            profile.get_name().to_string(),
+            profile.get_type().to_string(),
+            text(profile.get_issuer()),
+            text(profile.get_account()),
+            tags,
+            datetime(profile.get_created_at()),
+            datetime(profile.get_last_used_at()),
+            text(profile.get_notes()),
+        ]);
+    }
+    print_table(&rows);
+    process::exit(0);
+}
+
+// Print rows with aligned columns. The last column is not padded.
+fn print_table<const N: usize>(rows: &[[String; N]]) {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in rows {
+        let mut line = String::new();
+        for (i, cell) in row.iter().enumerate() {
+            if i + 1 == N {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{:width$}  ", cell, width = widths[i]));
+            }
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
 fn profile_remove(mfa: &mut Mfa, args: &Remove) {
     if let Err(err) = mfa.remove_profile(&args.profile) {
         eprintln!("failed remove profile: {}", err);
@@ -125,6 +708,570 @@ fn profile_remove(mfa: &mut Mfa, args: &Remove) {
     dump_config(mfa);
 }
 
+fn profile_counter(mfa: &mut Mfa, args: &Counter) {
+    match args.counter {
+        Some(counter) => {
+            if let Err(err) = mfa.set_counter(&args.profile, counter) {
+                eprintln!("failed to set counter: {}", err);
+                process::exit(6);
+            }
+
+            dump_config(mfa);
+        }
+        None => match mfa.get_counter(&args.profile) {
+            Ok(counter) => println!("{}", counter),
+            Err(err) => {
+                eprintln!("failed to get counter: {}", err);
+                process::exit(6);
+            }
+        },
+    }
+}
+
+fn profile_export(mfa: &Mfa, args: &Export) {
+    let filter = args.filters.to_filter();
+    let profiles = match mfa.get_profiles_by_filter(&args.profiles, &filter) {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            eprintln!("failed to export profiles: {}", err);
+            process::exit(5);
+        }
+    };
+    if profiles.is_empty() && !filter.is_empty() {
+        eprintln!("failed to export profiles: no profiles match the filter");
+        process::exit(5);
+    }
+
+    if args.encrypt && args.format != ExportFormat::Aegis {
+        eprintln!("--encrypt is available only with --format aegis");
+        process::exit(5);
+    }
+    if !args.encrypt {
+        confirm_revealing_secrets(args.yes);
+    }
+
+    match args.format {
+        ExportFormat::Uri => {
+            for profile in profiles {
+                match KeyUri::from_profile(profile) {
+                    Ok(key_uri) => println!("{}", key_uri),
+                    Err(err) => eprintln!("skipped {}: {}", profile.get_name(), err),
+                }
+            }
+        }
+        ExportFormat::Migration => export_migration(&profiles, args),
+        ExportFormat::Aegis => export_aegis(&profiles, args),
+    }
+}
+
+fn export_aegis(profiles: &[&config::Profile], args: &Export) {
+    let profiles = exportable_profiles(profiles, aegis::Vault::validate_profile);
+    let vault = if args.encrypt {
+        let password = prompt_new_password();
+        aegis::Vault::encrypt(&profiles, &password)
+    } else {
+        aegis::Vault::plain(&profiles)
+    };
+
+    match vault.and_then(|vault| vault.to_json()) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("failed to export profiles: {}", err);
+            process::exit(5);
+        }
+    }
+}
+
+// Skip profiles which can't be exported in the format and report them.
+fn exportable_profiles<'a>(
+    profiles: &[&'a config::Profile],
+    validate: impl Fn(&config::Profile) -> Result<(), config::ValidationError>,
+) -> Vec<&'a config::Profile> {
+    profiles
+        .iter()
+        .filter(|profile| match validate(profile) {
+            Ok(_) => true,
+            Err(err) => {
+                eprintln!("skipped {}: {}", profile.get_name(), err);
+                false
+            }
+        })
+        .copied()
+        .collect()
+}
+
+fn export_migration(profiles: &[&config::Profile], args: &Export) {
+    let profiles = exportable_profiles(profiles, migration::Payload::validate_profile);
+
+    let uris = match migration::Payload::export(&profiles, args.batch_size) {
+        Ok(uris) => uris,
+        Err(err) => {
+            eprintln!("failed to export profiles: {}", err);
+            process::exit(5);
+        }
+    };
+
+    for (i, uri) in uris.iter().enumerate() {
+        if !args.qr {
+            println!("{}", uri);
+            continue;
+        }
+
+        match qr::render_unicode(uri) {
+            Ok(rendered) => println!("QR code {}/{}\n{}", i + 1, uris.len(), rendered),
+            Err(err) => {
+                eprintln!("failed to render the QR code: {}", err);
+                process::exit(8);
+            }
+        }
+    }
+}
+
+fn profile_qr(mfa: &Mfa, args: &Qr) {
+    let key_uri = match mfa.get_profile_by_name(&args.profile) {
+        Some(profile) => KeyUri::from_profile(profile),
+        None => {
+            eprintln!("can't find the profile: {}", args.profile);
+            process::exit(5);
+        }
+    };
+    let uri = match key_uri {
+        Ok(key_uri) => key_uri.to_string(),
+        Err(err) => {
+            eprintln!("failed to build the URI: {}", err);
+            process::exit(5);
+        }
+    };
+
+    confirm_revealing_secrets(args.yes);
+
+    let path = match &args.output {
+        Some(path) => path,
+        None => match qr::render_unicode(&uri) {
+            Ok(rendered) => {
+                println!("{}", rendered);
+                return;
+            }
+            Err(err) => {
+                eprintln!("failed to render the QR code: {}", err);
+                process::exit(8);
+            }
+        },
+    };
+
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    let rendered = match extension.map(|ext| ext.to_lowercase()).as_deref() {
+        Some("png") => qr::render_png(&uri),
+        Some("svg") => qr::render_svg(&uri).map(String::into_bytes),
+        _ => {
+            eprintln!("the output file must be .png or .svg: {}", path.display());
+            process::exit(8);
+        }
+    };
+
+    let result = rendered.and_then(|data| qr::write_file(path, &data));
+    if let Err(err) = result {
+        eprintln!("failed to write the QR code: {}", err);
+        process::exit(8);
+    }
+}
+
+fn profile_import(mfa: &mut Mfa, args: &Import) {
+    let mut candidates = Vec::new();
+    let mut uris = Vec::new();
+
+    for path in &args.qr {
+        let source = path.display().to_string();
+        match qr::decode_image_file(path) {
+            Ok(contents) => {
+                for (i, content) in contents.into_iter().enumerate() {
+                    let label = format!("{} (QR code #{})", source, i + 1);
+                    match content {
+                        Ok(uri) => uris.push((label, uri)),
+                        Err(err) => candidates.push(Err(import::Skipped::new(&label, &err))),
+                    }
+                }
+            }
+            Err(err) => candidates.push(Err(import::Skipped::new(&source, &err))),
+        }
+    }
+    for (i, uri) in args.uri.iter().enumerate() {
+        uris.push((format!("URI #{}", i + 1), uri.to_string()));
+    }
+    candidates.extend(import::candidates_from_uris(&uris));
+    for path in &args.aegis {
+        candidates.extend(candidates_from_file(path, read_aegis));
+    }
+    for path in &args.andotp {
+        candidates.extend(candidates_from_file(path, read_andotp));
+    }
+    for path in &args.twofas {
+        candidates.extend(candidates_from_file(path, read_twofas));
+    }
+    for path in &args.bitwarden {
+        candidates.extend(candidates_from_file(path, read_bitwarden));
+    }
+    let columns = args.csv_columns.clone().unwrap_or_default();
+    for path in &args.csv {
+        candidates.extend(candidates_from_file(path, |path| read_csv(path, &columns)));
+    }
+    for path in &args.kdbx {
+        candidates.extend(candidates_from_file(path, |path| {
+            read_kdbx(path, args.kdbx_key_file.as_deref(), args.kdbx_no_password)
+        }));
+    }
+    if let Some(store) = &args.pass {
+        match store.clone().or_else(pass::default_store) {
+            Some(store) => candidates.extend(candidates_from_pass(&store)),
+            None => candidates.push(Err(import::Skipped::new(
+                "password-store",
+                "The store is not found. Give the directory.",
+            ))),
+        }
+    }
+
+    let report = mfa.import_profiles(candidates);
+    print!("{}", report);
+
+    if args.dry_run {
+        println!("Nothing is saved because of --dry-run");
+        return;
+    }
+    if !report.get_imported().is_empty() {
+        dump_config(mfa);
+    }
+}
+
+// Build candidates from an export file of another authenticator.
+// The file is reported as skipped if it can't be read.
+fn candidates_from_file<E: import::Entry>(
+    path: &Path,
+    read: impl Fn(&Path) -> Result<Vec<E>, String>,
+) -> Vec<import::Candidate> {
+    match read(path) {
+        Ok(entries) => import::candidates_from_entries(&entries),
+        Err(err) => vec![Err(import::Skipped::new(&path.display().to_string(), &err))],
+    }
+}
+
+// Build candidates from entries of a password-store which have otpauth URIs.
+// Entries which can't be decrypted are reported as skipped.
+fn candidates_from_pass(store: &Path) -> Vec<import::Candidate> {
+    let names = match pass::find(store) {
+        Ok(names) => names,
+        Err(err) => {
+            return vec![Err(import::Skipped::new(
+                &store.display().to_string(),
+                &err,
+            ))]
+        }
+    };
+
+    let mut candidates = Vec::new();
+    for name in names {
+        match pass::decrypt(&pass::entry_path(store, &name)) {
+            Ok(content) => candidates.extend(import::candidates_from_entries(&pass::parse(
+                &name, &content,
+            ))),
+            Err(err) => candidates.push(Err(import::Skipped::new(&name, &err))),
+        }
+    }
+    candidates
+}
+
+// Read an Aegis export and decrypt it if it is encrypted.
+fn read_aegis(path: &Path) -> Result<Vec<aegis::Entry>, String> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) => return Err(err.to_string()),
+    };
+    let vault = aegis::Vault::parse(&data)?;
+    let database = if vault.is_encrypted() {
+        let password = prompt_password(&format!("Password for {}: ", path.display()))?;
+        vault.database(Some(&password))?
+    } else {
+        vault.database(None)?
+    };
+
+    Ok(database.into_entries())
+}
+
+// Read an andOTP backup and decrypt it if it is encrypted.
+fn read_andotp(path: &Path) -> Result<Vec<andotp::Entry>, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Err(err.to_string()),
+    };
+    if !andotp::is_encrypted(&data) {
+        return andotp::parse(&data, None);
+    }
+
+    let password = prompt_password(&format!("Password for {}: ", path.display()))?;
+    andotp::parse(&data, Some(&password))
+}
+
+// Read a 2FAS backup and decrypt it if it is protected with a password.
+fn read_twofas(path: &Path) -> Result<Vec<twofas::Service>, String> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(err) => return Err(err.to_string()),
+    };
+    let backup = twofas::Backup::parse(&data)?;
+    if !backup.is_encrypted() {
+        return backup.services(None);
+    }
+
+    let password = prompt_password(&format!("Password for {}: ", path.display()))?;
+    backup.services(Some(&password))
+}
+
+// Read a Bitwarden export.
+fn read_bitwarden(path: &Path) -> Result<Vec<bitwarden::Item>, String> {
+    match fs::read_to_string(path) {
+        Ok(data) => Ok(bitwarden::Export::parse(&data)?.into_items()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// Read CSV with the column mapping.
+fn read_csv(path: &Path, columns: &csv_import::Columns) -> Result<Vec<csv_import::Row>, String> {
+    match fs::read(path) {
+        Ok(data) => csv_import::parse(&data, columns),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// Read a KeePass database with the password and/or the key file.
+//
+// An empty password is ignored if the key file is given.
+fn read_kdbx(
+    path: &Path,
+    key_file: Option<&Path>,
+    no_password: bool,
+) -> Result<Vec<kdbx::Entry>, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Err(err.to_string()),
+    };
+    let key_file = match key_file.map(fs::read) {
+        Some(Ok(key_file)) => Some(key_file),
+        Some(Err(err)) => return Err(format!("failed to read the key file: {}", err)),
+        None => None,
+    };
+
+    let password = if no_password {
+        None
+    } else {
+        Some(prompt_password(&format!(
+            "Password for {}: ",
+            path.display()
+        ))?)
+    };
+    let password = match password {
+        Some(password) if password.is_empty() && key_file.is_some() => None,
+        password => password,
+    };
+    kdbx::open(&data, &kdbx::Key::new(password.as_deref(), key_file))
+}
+
+// Ask a password without echo back.
+fn prompt_password(prompt: &str) -> Result<String, String> {
+    match rpassword::prompt_password(prompt) {
+        Ok(password) => Ok(password),
+        Err(err) => Err(format!("failed to read the password: {}", err)),
+    }
+}
+
+// Ask a new password twice.
+// exit process with code 7 if the passwords don't match.
+fn prompt_new_password() -> String {
+    let passwords = prompt_password("New password: ")
+        .and_then(|password| Ok((password, prompt_password("Confirm password: ")?)));
+    let (password, confirmation) = match passwords {
+        Ok(passwords) => passwords,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(7);
+        }
+    };
+
+    if password.is_empty() || password != confirmation {
+        eprintln!("Passwords are empty or don't match");
+        process::exit(7);
+    }
+
+    password
+}
+
+// Encrypt with a passphrase, or to recipients if they are given.
+// exit process with code 9 if the profile file is already encrypted.
+fn vault_encrypt(mfa: &mut Mfa, args: &VaultEncrypt) {
+    if mfa.is_encrypted() {
+        eprintln!("The profile file is already encrypted");
+        process::exit(9);
+    }
+
+    let mut recipients = args.recipient.clone();
+    for path in &args.recipients_file {
+        match fs::read_to_string(path) {
+            Ok(data) => recipients.extend(
+                data.lines()
+                    .map(|line| line.trim())
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| line.to_string()),
+            ),
+            Err(err) => {
+                eprintln!("failed to read {}: {}", path.display(), err);
+                process::exit(9);
+            }
+        }
+    }
+
+    let result = if recipients.is_empty() {
+        mfa.encrypt(&new_passphrase())
+    } else {
+        mfa.encrypt_to_recipients(&recipients)
+    };
+    if let Err(err) = result {
+        eprintln!("failed to encrypt the profile file: {}", err);
+        process::exit(9);
+    }
+
+    dump_config(mfa);
+    println!("Encrypted the profile file");
+}
+
+// exit process with code 9 if the profile file is not encrypted to recipients.
+fn vault_recipients_list(mfa: &Mfa) {
+    match mfa.get_recipients() {
+        Ok(recipients) => recipients
+            .iter()
+            .for_each(|recipient| println!("{}", recipient)),
+        Err(err) => {
+            eprintln!("failed to get recipients: {}", err);
+            process::exit(9);
+        }
+    }
+}
+
+// exit process with code 9 if the recipients can't be added.
+fn vault_recipients_add(mfa: &mut Mfa, args: &RecipientKeys) {
+    for recipient in &args.recipients {
+        if let Err(err) = mfa.add_recipient(recipient) {
+            eprintln!("failed to add the recipient: {}", err);
+            process::exit(9);
+        }
+    }
+
+    dump_config(mfa);
+    println!("Encrypted the profile file again");
+}
+
+// exit process with code 9 if the recipients can't be removed.
+fn vault_recipients_remove(mfa: &mut Mfa, args: &RecipientKeys) {
+    for recipient in &args.recipients {
+        if let Err(err) = mfa.remove_recipient(recipient) {
+            eprintln!("failed to remove the recipient: {}", err);
+            process::exit(9);
+        }
+    }
+
+    dump_config(mfa);
+    println!("Encrypted the profile file again");
+    println!("Removed recipients may still have old copies. Rotate the secret keys if needed.");
+}
+
+// exit process with code 9 if the permissions can't be fixed.
+fn vault_fix_permissions() {
+    let fixed = match mfa::fix_permissions() {
+        Ok(fixed) => fixed,
+        Err(err) => {
+            eprintln!("failed to fix the permissions: {}", err);
+            process::exit(9);
+        }
+    };
+
+    if fixed.is_empty() {
+        println!("The permissions are already private");
+    }
+    for path in fixed {
+        println!("Made private: {}", path.display());
+    }
+}
+
+// exit process with code 9 if the profile file is not encrypted.
+fn vault_decrypt(mfa: &mut Mfa) {
+    if let Err(err) = mfa.decrypt() {
+        eprintln!("failed to decrypt the profile file: {}", err);
+        process::exit(9);
+    }
+
+    dump_config(mfa);
+    println!("Decrypted the profile file");
+}
+
+// exit process with code 9 if the profile file is not encrypted with a passphrase.
+fn vault_passphrase(mfa: &mut Mfa) {
+    if !mfa.is_encrypted_with_passphrase() {
+        eprintln!("The profile file is not encrypted with a passphrase");
+        process::exit(9);
+    }
+
+    let passphrase = new_passphrase();
+    if let Err(err) = mfa.change_passphrase(&passphrase) {
+        eprintln!("failed to change the passphrase: {}", err);
+        process::exit(9);
+    }
+
+    dump_config(mfa);
+    println!("Changed the passphrase");
+}
+
+// Get a new passphrase from MFA_CLI_NEW_PASSPHRASE or ask it twice.
+// exit process with code 7 if the passphrases don't match.
+fn new_passphrase() -> String {
+    if let Ok(passphrase) = std::env::var("MFA_CLI_NEW_PASSPHRASE") {
+        return passphrase;
+    }
+
+    let passphrases = prompt_password("New passphrase: ")
+        .and_then(|passphrase| Ok((passphrase, prompt_password("Confirm passphrase: ")?)));
+    let (passphrase, confirmation) = match passphrases {
+        Ok(passphrases) => passphrases,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(7);
+        }
+    };
+
+    if passphrase.is_empty() || passphrase != confirmation {
+        eprintln!("Passphrases are empty or don't match");
+        process::exit(7);
+    }
+
+    passphrase
+}
+
+// Ask the user whether to continue because secret keys will be revealed.
+// exit process with code 7 if the user doesn't agree.
+fn confirm_revealing_secrets(yes: bool) {
+    if yes {
+        return;
+    }
+
+    eprint!("This will reveal secret keys. Continue? [y/N] ");
+    io::stderr().flush().unwrap();
+
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        process::exit(7);
+    }
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        eprintln!("Canceled");
+        process::exit(7);
+    }
+}
+
 // call Mfa#dump()
 // exit process with code 3 if failed dump.
 fn dump_config(mfa: &Mfa) {
@@ -134,28 +1281,226 @@ fn dump_config(mfa: &Mfa) {
     }
 }
 
-fn show(mfa: &Mfa, args: &Show) {
-    let profile = &args.profile;
+// Show the code from the agent.
+// It returns to show the code without the agent if the agent is not running or locked.
+// exit process with code 4 if the agent can't show the code.
+#[cfg(unix)]
+fn show_with_agent(socket: &Path, args: &Show) {
+    let request = agent::Request::Code {
+        profile: args.profile.clone(),
+        watch: args.watch,
+        filter: args.filters.to_filter(),
+        exact: args.exact,
+    };
 
-    let secret = match mfa.get_secret_by_name(&args.profile) {
-        Some(secret) => secret,
-        None => {
-            eprintln!("can't get the secret that profile: {}", profile);
-            process::exit(4);
+    let mut first = true;
+    loop {
+        let code = match agent::request(socket, &request) {
+            Ok(agent::Response::Code { code }) => code,
+            Ok(agent::Response::Error { message }) => {
+                eprintln!("failed to show code: {}", message);
+                process::exit(4);
+            }
+            Ok(_) | Err(_) if first => return,
+            Ok(_) => {
+                eprintln!("\nThe agent is locked");
+                process::exit(4);
+            }
+            Err(err) => {
+                eprintln!("\n{}", err);
+                process::exit(4);
+            }
+        };
+        first = false;
+
+        print!("{}", code);
+        io::stdout().flush().unwrap();
+
+        if args.watch {
+            thread::sleep(time::Duration::from_secs(1));
+            print!("\r");
+        } else {
+            println!();
+            break;
         }
+    }
+    process::exit(0);
+}
+
+// Spawn the agent and unlock it.
+// Print the env variable for `eval "$(mfa-cli agent start)"`.
+// exit process with code 10 if the agent doesn't start.
+#[cfg(unix)]
+fn agent_start(args: &AgentServe) {
+    let socket = match &args.socket {
+        Some(socket) => socket.clone(),
+        None => agent::new_socket_path().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(10);
+        }),
     };
 
+    let spawned = std::env::current_exe().and_then(|exe| {
+        process::Command::new(exe)
+            .args(["agent", "serve", "--timeout", &args.timeout.to_string()])
+            .arg("--socket")
+            .arg(&socket)
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            // 端末のシグナルを受けないように別のプロセスグループにする
+            .process_group(0)
+            .spawn()
+    });
+    let child = match spawned {
+        Ok(child) => child,
+        Err(err) => {
+            eprintln!("failed to start the agent: {}", err);
+            process::exit(10);
+        }
+    };
+
+    for _ in 0..50 {
+        if socket.exists() {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    if let Err(err) = agent_unlock(&socket) {
+        // 使えないエージェントを残さない
+        let _ = agent::request(&socket, &agent::Request::Stop);
+        eprintln!("failed to unlock the agent: {}", err);
+        process::exit(10);
+    }
+
+    println!(
+        "{}={}; export {};",
+        agent::SOCKET_ENV,
+        socket.display(),
+        agent::SOCKET_ENV
+    );
+    println!("echo Agent pid {};", child.id());
+}
+
+// exit process with code 10 if the agent stops with an error.
+#[cfg(unix)]
+fn agent_serve(args: &AgentServe) {
+    let socket = match &args.socket {
+        Some(socket) => socket.clone(),
+        None => agent::new_socket_path().unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(10);
+        }),
+    };
+    let timeout = match args.timeout {
+        0 => None,
+        timeout => Some(time::Duration::from_secs(timeout)),
+    };
+
+    eprintln!("{}={}", agent::SOCKET_ENV, socket.display());
+    let result = agent::Agent::new(timeout).serve(&socket);
+    agent::remove_socket_dir(&socket);
+    if let Err(err) = result {
+        eprintln!("failed to run the agent: {}", err);
+        process::exit(10);
+    }
+}
+
+// Unlock the agent. The passphrase and the identity passphrase are asked if the agent needs them.
+#[cfg(unix)]
+fn agent_unlock(socket: &Path) -> Result<(), String> {
+    let mut passphrase = None;
+    let mut identity_passphrase = None;
     loop {
-        let code = match totp::totp(&secret) {
-            Ok(code) => code,
-            Err(err) => panic!("{}", err),
+        let request = agent::Request::Unlock {
+            passphrase: passphrase.clone(),
+            identity_passphrase: identity_passphrase.clone(),
+        };
+        match agent::request(socket, &request)? {
+            agent::Response::Ok => return Ok(()),
+            agent::Response::NeedPassphrase if passphrase.is_none() => {
+                passphrase = Some(mfa::read_passphrase()?);
+            }
+            agent::Response::NeedIdentityPassphrase if identity_passphrase.is_none() => {
+                identity_passphrase = Some(mfa::read_identity_passphrase()?);
+            }
+            agent::Response::Error { message } => return Err(message),
+            response => return Err(format!("unexpected response: {:?}", response)),
+        }
+    }
+}
+
+// exit process with code 10 if the agent fails.
+#[cfg(unix)]
+fn agent_request(request: &agent::Request) {
+    match agent::request(&agent_socket(), request) {
+        Ok(agent::Response::Ok) => {}
+        Ok(response) => {
+            eprintln!("The agent failed: {:?}", response);
+            process::exit(10);
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(10);
+        }
+    }
+}
+
+// exit process with code 10 if MFA_CLI_AGENT_SOCK is not set.
+#[cfg(unix)]
+fn agent_socket() -> PathBuf {
+    match agent::socket_from_env() {
+        Some(socket) => socket,
+        None => {
+            eprintln!("{} is not set", agent::SOCKET_ENV);
+            process::exit(10);
+        }
+    }
+}
+
+fn show(mfa: &mut Mfa, args: &Show) {
+    let profile =
+        &match mfa.resolve_profile_name(&args.profile, &args.filters.to_filter(), args.exact) {
+            Ok(profile) => profile,
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(4);
+            }
         };
+
+    if mfa.get_secret_by_name(profile).is_none() {
+        eprintln!("can't get the secret that profile: {}", profile);
+        process::exit(4);
+    }
+
+    let profile_type = mfa.get_profile_by_name(profile).map(|p| p.get_type());
+    if args.watch && profile_type == Some(ProfileType::Hotp) {
+        eprintln!("can't watch a HOTP profile: {}", profile);
+        process::exit(4);
+    }
+
+    // the counter of HOTP and the time of use are dumped
+    let mut code = match mfa.consume_code_by_name(profile) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("failed to show code: {}", err);
+            process::exit(3);
+        }
+    };
+
+    // 見ている間は書き込まないので他のコマンドを待たせない
+    mfa.release_lock();
+    loop {
         print!("{}", code);
         io::stdout().flush().unwrap();
 
         if args.watch {
             thread::sleep(time::Duration::from_secs(1));
             print!("\r");
+            code = match mfa.get_code_by_name(profile) {
+                Ok(code) => code,
+                Err(err) => panic!("{}", err),
+            };
         } else {
             println!();
             break;
@@ -1,16 +1,45 @@
+use super::age_vault;
 use super::config;
+use super::config::{ProfileType, ValidationError};
+use super::datetime;
+use super::encryption;
+use super::filter::Filter;
+use super::fuzzy;
+use super::hotp;
+use super::import;
+use super::schema;
+use super::storage;
 use super::totp;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
 use std::env;
 use std::fmt;
-use std::fs::{DirBuilder, File};
+use std::fs::{self, DirBuilder, File};
 use std::io::prelude::*;
+use std::io::BufReader;
 use std::path::Path;
+use std::time::SystemTime;
 
 // 設定ファイルのルートディレクトリ
 const SAVE_DIR_NAME: &str = "mfa-cli";
 const HIDDEN_SAVE_DIR_NAME: &str = ".mfa-cli";
 // 設定ファイル名
 const CONFIG_FILE_NAME: &str = "profile";
+// 利用日時を書き込む最短の間隔 (秒)
+const LAST_USED_INTERVAL: u64 = 60;
+// 読み書きを直列化するロックファイル名
+const LOCK_FILE_NAME: &str = "profile.lock";
+// 自動化のためにパスフレーズを渡す環境変数
+const PASSPHRASE_ENV: &str = "MFA_CLI_PASSPHRASE";
+const PASSPHRASE_FD_ENV: &str = "MFA_CLI_PASSPHRASE_FD";
+// 他のユーザーが読める設定ファイルを拒否するか警告するかを指定する環境変数
+pub const PERMISSIONS_ENV: &str = "MFA_CLI_PERMISSIONS";
+
+// The error when the passphrase is required but not given
+pub const PASSPHRASE_REQUIRED: &str = "The passphrase is required.";
+// The error when another command changed the profile file while the lock is released
+pub const PROFILE_FILE_CHANGED: &str =
+    "The profile file is changed by another command. Read it again.";
 
 // for using print Profile
 #[derive(Debug)]
@@ -34,6 +63,20 @@ impl Profile {
 pub struct Mfa {
     config: config::Config,
     dump_file: DumpFile,
+    // How the profile file is encrypted. None if the file is plaintext.
+    protection: Option<Protection>,
+    // Held from reading the profile file to dumping it, so that parallel commands don't lose changes.
+    lock: Option<storage::Lock>,
+    // The hash of the profile file which is read or written last.
+    // dump doesn't overwrite changes made by another command while the lock is released.
+    digest: RefCell<Option<Vec<u8>>>,
+}
+
+// 設定ファイルの暗号化の方法
+#[derive(Debug)]
+enum Protection {
+    Passphrase(encryption::Key),
+    Age(age_vault::Vault),
 }
 
 impl Mfa {
@@ -41,14 +84,72 @@ impl Mfa {
         let mut this = Self {
             config: Default::default(),
             dump_file: Default::default(),
+            protection: None,
+            lock: None,
+            digest: Default::default(),
+        };
+
+        match this.setup(read_passphrase, read_identities) {
+            Ok(_) => Ok(this),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Build with the given passphrases instead of asking them.
+    //
+    // It fails with PASSPHRASE_REQUIRED if the profile file is encrypted with a passphrase
+    // and no passphrase is given, or with age_vault::IDENTITY_PASSPHRASE_REQUIRED
+    // if the identity is an encrypted SSH key and its passphrase is not given.
+    pub fn with_passphrase(
+        passphrase: Option<&str>,
+        identity_passphrase: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut this = Self {
+            config: Default::default(),
+            dump_file: Default::default(),
+            protection: None,
+            lock: None,
+            digest: Default::default(),
         };
 
-        match this.setup() {
+        let identities = || {
+            read_identities_with(given(
+                identity_passphrase,
+                age_vault::IDENTITY_PASSPHRASE_REQUIRED,
+            ))
+        };
+        match this.setup(given(passphrase, PASSPHRASE_REQUIRED), identities) {
             Ok(_) => Ok(this),
             Err(err) => Err(err),
         }
     }
 
+    // Build with the profile file in the directory and the identity instead of the env variables.
+    #[cfg(test)]
+    pub(crate) fn in_dir(
+        dir: &Path,
+        identity: Option<&Path>,
+        identity_passphrase: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut this = Self {
+            dump_file: DumpFile {
+                dir: dir.to_path_buf().into_boxed_path(),
+                file_name: CONFIG_FILE_NAME,
+            },
+            ..Default::default()
+        };
+
+        let identities = || match identity {
+            Some(identity) => age_vault::read_identities(
+                identity,
+                given(identity_passphrase, age_vault::IDENTITY_PASSPHRASE_REQUIRED),
+            ),
+            None => Err(String::from("The identity is not given.")),
+        };
+        this.setup(given(None, PASSPHRASE_REQUIRED), identities)?;
+        Ok(this)
+    }
+
     // Build new profile and register.
     pub fn register_profile(&mut self, account_name: &str, secret: &str) -> Result<(), String> {
         match self.config.new_profile(account_name, secret) {
@@ -57,6 +158,66 @@ impl Mfa {
         }
     }
 
+    // Register a profile which is built by the caller.
+    pub fn add_profile(&mut self, profile: config::Profile) -> Result<(), String> {
+        match self.config.push_profile(profile) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    // Register imported profiles.
+    //
+    // Each profile is validated like a profile which is added by hand.
+    // Profiles that fail validation are skipped and reported with the reason.
+    //
+    // When the name is already registered, the profile is skipped if it has the same secret.
+    // Otherwise it is registered with a unique name.
+    pub fn import_profiles(&mut self, candidates: Vec<import::Candidate>) -> import::Report {
+        let mut report: import::Report = Default::default();
+
+        for candidate in candidates {
+            let mut profile = match candidate {
+                Ok(profile) => profile,
+                Err(skipped) => {
+                    report.push_skipped(skipped);
+                    continue;
+                }
+            };
+
+            let name = profile.get_name().to_string();
+            let err = match self.config.push_profile(profile.clone()) {
+                Ok(_) => {
+                    report.push_imported(&name);
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            if let ValidationError::Deplication(_) = err {
+                if self.get_secret_by_name(&name) == profile.get_secret() {
+                    report.push_skipped(import::Skipped::new(
+                        &name,
+                        "The same profile already exists.",
+                    ));
+                    continue;
+                }
+
+                let unique_name = self.config.unique_name(&name);
+                profile.set_name(&unique_name);
+                match self.config.push_profile(profile) {
+                    Ok(_) => report.push_renamed(&name, &unique_name),
+                    Err(err) => report.push_skipped(import::Skipped::new(&name, &err.to_string())),
+                }
+                continue;
+            }
+
+            report.push_skipped(import::Skipped::new(&name, &err.to_string()));
+        }
+
+        report
+    }
+
     // Get all of profile list
     pub fn list_profiles(&self) -> Vec<Profile> {
         self.config
@@ -66,6 +227,25 @@ impl Mfa {
             .collect()
     }
 
+    // Edit a profile. The edited profile is validated before it is saved.
+    pub fn edit_profile(
+        &mut self,
+        profile_name: &str,
+        edit: impl FnOnce(&mut config::Profile),
+    ) -> Result<(), String> {
+        let mut profile = match self.config.find_by_name(profile_name) {
+            Some(profile) => profile.clone(),
+            None => return Err(format!("can't find the profile: {}", profile_name)),
+        };
+
+        edit(&mut profile);
+        self.config.replace_profile(profile_name, profile)
+    }
+
+    pub fn rename_profile(&mut self, profile_name: &str, new_name: &str) -> Result<(), String> {
+        self.config.rename_profile(profile_name, new_name)
+    }
+
     pub fn remove_profile(&mut self, profile_name: &str) -> Result<(), String> {
         self.config.remove_profile(profile_name)
     }
@@ -75,36 +255,336 @@ impl Mfa {
         self.config.get_secret_by_name(profile_name)
     }
 
+    // Get a profile with a profile name.
+    pub fn get_profile_by_name(&self, profile_name: &str) -> Option<&config::Profile> {
+        self.config.find_by_name(profile_name)
+    }
+
+    // Get profiles with profile names.
+    // It returns all of profiles if no names are given.
+    pub fn get_profiles_by_names(
+        &self,
+        profile_names: &[String],
+    ) -> Result<Vec<&config::Profile>, String> {
+        if profile_names.is_empty() {
+            return Ok(self.config.get_profiles().iter().collect());
+        }
+
+        profile_names
+            .iter()
+            .map(|name| match self.config.find_by_name(name) {
+                Some(profile) => Ok(profile),
+                None => Err(format!("can't find the profile: {}", name)),
+            })
+            .collect()
+    }
+
+    // Get profiles which match the filter among the profiles with the names.
+    // The filter is applied to all of profiles if no names are given.
+    pub fn get_profiles_by_filter(
+        &self,
+        profile_names: &[String],
+        filter: &Filter,
+    ) -> Result<Vec<&config::Profile>, String> {
+        filter.apply(self.get_profiles_by_names(profile_names)?)
+    }
+
+    // Find the name of the profile which is chosen by the filter.
+    //
+    // Unless exact, a unique prefix or a unique fuzzy match of the name is also accepted.
+    // e.g. "gh" resolves to "github-work"
+    // Candidates are listed from the best if they are ambiguous.
+    pub fn resolve_profile_name(
+        &self,
+        profile_name: &str,
+        filter: &Filter,
+        exact: bool,
+    ) -> Result<String, String> {
+        let names: Vec<&str> = filter
+            .apply(self.config.get_profiles())?
+            .into_iter()
+            .map(|profile| profile.get_name().as_str())
+            .collect();
+        if names.contains(&profile_name) {
+            return Ok(profile_name.to_string());
+        }
+        if exact {
+            return Err(format!("can't find the profile: {}", profile_name));
+        }
+
+        // 前方一致を優先し、なければ曖昧一致
+        let query = profile_name.to_lowercase();
+        let prefixed: Vec<&str> = names
+            .iter()
+            .copied()
+            .filter(|name| name.to_lowercase().starts_with(&query))
+            .collect();
+        let candidates = if prefixed.is_empty() {
+            fuzzy::rank(profile_name, names)
+        } else {
+            fuzzy::rank(profile_name, prefixed)
+        };
+
+        match candidates.as_slice() {
+            [] => Err(format!("can't find the profile: {}", profile_name)),
+            [name] => Ok(name.to_string()),
+            candidates => Err(format!(
+                "the profile name is ambiguous: {}\n{}",
+                profile_name,
+                candidates
+                    .iter()
+                    .map(|name| format!("  {}", name))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            )),
+        }
+    }
+
     // Get the authentication code with a profile name.
+    //
+    // The counter of a HOTP profile doesn't move.
     pub fn get_code_by_name(&self, profile_name: &str) -> Result<String, String> {
-        match self.get_secret_by_name(profile_name) {
-            Some(secret) => totp::totp(secret.as_ref()),
-            None => Err(format!(
-                "can't get the secret that profile: {}",
-                profile_name
+        let profile = match self.config.find_by_name(profile_name) {
+            Some(profile) => profile,
+            None => return Err(format!("can't find the profile: {}", profile_name)),
+        };
+
+        let secret = match profile.get_secret() {
+            Some(secret) => secret,
+            None => {
+                return Err(format!(
+                    "can't get the secret that profile: {}",
+                    profile_name
+                ))
+            }
+        };
+
+        match profile.get_type() {
+            ProfileType::Totp => totp::totp(
+                secret.as_ref(),
+                profile.get_digits(),
+                profile.get_period(),
+                profile.get_t0(),
+                profile.get_algorithm(),
+            ),
+            ProfileType::Hotp => hotp::gen_hotp(
+                secret.as_ref(),
+                profile.get_counter(),
+                profile.get_digits(),
+                profile.get_algorithm(),
+            ),
+            ProfileType::Steam => totp::steam(
+                secret.as_ref(),
+                profile.get_period(),
+                profile.get_t0(),
+                profile.get_algorithm(),
+            ),
+        }
+    }
+
+    // Get the authentication code with a profile name and use it up.
+    //
+    // The counter of a HOTP profile is incremented and dumped before the code is returned,
+    // so that the same code is never shown twice.
+    // If dumping fails, the counter is rolled back and the code is not returned.
+    //
+    // The time of use is recorded too. For other profiles it is recorded at most once a minute
+    // and the code is returned even if dumping fails.
+    pub fn consume_code_by_name(&mut self, profile_name: &str) -> Result<String, String> {
+        let code = self.get_code_by_name(profile_name)?;
+
+        let profile = match self.config.find_by_name_mut(profile_name) {
+            Some(profile) => profile,
+            None => return Err(format!("can't find the profile: {}", profile_name)),
+        };
+        let now = datetime::now();
+        if profile.get_type() != ProfileType::Hotp {
+            let last_used = profile.get_last_used_at().and_then(datetime::to_unix);
+            let now_unix = datetime::to_unix(&now).unwrap_or(0);
+            if last_used.map_or(true, |last| last + LAST_USED_INTERVAL <= now_unix) {
+                profile.set_last_used_at(now);
+                // 利用日時は記録できなくてもコードは表示する
+                let _ = self.dump();
+            }
+            return Ok(code);
+        }
+
+        profile.set_last_used_at(now);
+        let counter = profile.get_counter();
+        match counter.checked_add(1) {
+            Some(next) => profile.set_counter(next),
+            None => return Err(String::from("The counter reaches the maximum value")),
+        }
+
+        if let Err(err) = self.dump() {
+            self.set_counter(profile_name, counter)?;
+            return Err(err);
+        }
+
+        Ok(code)
+    }
+
+    // Get the counter of a HOTP profile.
+    pub fn get_counter(&self, profile_name: &str) -> Result<u64, String> {
+        match self.config.find_by_name(profile_name) {
+            Some(profile) if profile.get_type() == ProfileType::Hotp => Ok(profile.get_counter()),
+            Some(_) => Err(format!("The profile is not HOTP: {}", profile_name)),
+            None => Err(format!("can't find the profile: {}", profile_name)),
+        }
+    }
+
+    // Set the counter of a HOTP profile.
+    pub fn set_counter(&mut self, profile_name: &str, counter: u64) -> Result<(), String> {
+        match self.config.find_by_name_mut(profile_name) {
+            Some(profile) if profile.get_type() == ProfileType::Hotp => {
+                profile.set_counter(counter);
+                Ok(())
+            }
+            Some(_) => Err(format!("The profile is not HOTP: {}", profile_name)),
+            None => Err(format!("can't find the profile: {}", profile_name)),
+        }
+    }
+
+    // Whether the profile file is encrypted with a passphrase or to age recipients.
+    pub fn is_encrypted(&self) -> bool {
+        self.protection.is_some()
+    }
+
+    // Whether the profile file is encrypted with a passphrase.
+    pub fn is_encrypted_with_passphrase(&self) -> bool {
+        matches!(self.protection, Some(Protection::Passphrase(_)))
+    }
+
+    // Encrypt the profile file with a passphrase from the next dump.
+    pub fn encrypt(&mut self, passphrase: &str) -> Result<(), String> {
+        if self.is_encrypted() {
+            return Err(String::from("The profile file is already encrypted."));
+        }
+
+        self.protection = Some(Protection::Passphrase(encryption::Key::new(passphrase)?));
+        Ok(())
+    }
+
+    // Encrypt the profile file to age recipients from the next dump.
+    pub fn encrypt_to_recipients(&mut self, recipients: &[String]) -> Result<(), String> {
+        if self.is_encrypted() {
+            return Err(String::from("The profile file is already encrypted."));
+        }
+
+        self.protection = Some(Protection::Age(age_vault::Vault::new(recipients)?));
+        Ok(())
+    }
+
+    // Write the profile file in plaintext from the next dump.
+    pub fn decrypt(&mut self) -> Result<(), String> {
+        if !self.is_encrypted() {
+            return Err(String::from("The profile file is not encrypted."));
+        }
+
+        self.protection = None;
+        Ok(())
+    }
+
+    // Change the passphrase of the encrypted profile file.
+    pub fn change_passphrase(&mut self, passphrase: &str) -> Result<(), String> {
+        match &mut self.protection {
+            Some(Protection::Passphrase(key)) => key.rewrap(passphrase),
+            Some(Protection::Age(_)) => Err(String::from(
+                "The profile file is encrypted to recipients, not with a passphrase.",
+            )),
+            None => Err(String::from("The profile file is not encrypted.")),
+        }
+    }
+
+    // Get recipients of the age vault.
+    pub fn get_recipients(&self) -> Result<&Vec<String>, String> {
+        match &self.protection {
+            Some(Protection::Age(vault)) => Ok(vault.get_recipients()),
+            _ => Err(String::from(
+                "The profile file is not encrypted to recipients.",
+            )),
+        }
+    }
+
+    // Add a recipient of the age vault. It is encrypted again at the next dump.
+    pub fn add_recipient(&mut self, recipient: &str) -> Result<(), String> {
+        self.vault_mut_or_err()?.add_recipient(recipient)
+    }
+
+    // Remove a recipient of the age vault. It is encrypted again at the next dump.
+    pub fn remove_recipient(&mut self, recipient: &str) -> Result<(), String> {
+        self.vault_mut_or_err()?.remove_recipient(recipient)
+    }
+
+    fn vault_mut_or_err(&mut self) -> Result<&mut age_vault::Vault, String> {
+        match &mut self.protection {
+            Some(Protection::Age(vault)) => Ok(vault),
+            _ => Err(String::from(
+                "The profile file is not encrypted to recipients.",
             )),
         }
     }
 
     // Dump config to file
+    //
+    // It is encrypted if the profile file is encrypted.
     pub fn dump(&self) -> Result<(), String> {
         let config_data = match self.config.serialize() {
             Ok(data) => data,
             Err(err) => return Err(err),
         };
+        let config_data = match &self.protection {
+            Some(Protection::Passphrase(key)) => key.seal(&config_data)?,
+            Some(Protection::Age(vault)) => vault.seal(&config_data)?,
+            None => config_data,
+        };
 
-        let mut file = match File::create(self.dump_file.path()) {
-            Ok(file) => file,
-            Err(err) => return Err(err.to_string()),
+        // ロックを手放していれば書き込む間だけ取り直す
+        let _lock = match self.lock {
+            Some(_) => None,
+            None => {
+                let lock = storage::Lock::acquire(&self.dump_file.lock_path())?;
+                // 手放している間に他のコマンドが書き換えていれば上書きしない
+                if self.is_changed() {
+                    return Err(String::from(PROFILE_FILE_CHANGED));
+                }
+                Some(lock)
+            }
         };
-        match file.write_all(config_data.as_bytes()) {
-            Ok(()) => Ok(()),
-            Err(err) => Err(err.to_string()),
-        }
+        storage::write_atomic(&self.dump_file.path(), config_data.as_bytes())?;
+        *self.digest.borrow_mut() = Some(digest(config_data.as_bytes()));
+        Ok(())
+    }
+
+    // Whether the profile file is changed since it is read or written last.
+    pub fn is_changed(&self) -> bool {
+        let current = fs::read(self.dump_file.path())
+            .ok()
+            .map(|data| digest(&data));
+        current != *self.digest.borrow()
+    }
+
+    // Release the lock of the profile file.
+    //
+    // Call it before waiting long, e.g. watching codes, not to block other commands.
+    // dump locks the file again while it writes.
+    pub fn release_lock(&mut self) {
+        self.lock = None;
     }
 
     // Restore config from file
+    //
+    // The passphrase is read if the file is encrypted with a passphrase.
+    // The identity is read if the file is encrypted to age recipients.
     pub fn restore(&mut self) -> Result<(), String> {
+        self.restore_with(read_passphrase, read_identities)
+    }
+
+    fn restore_with(
+        &mut self,
+        passphrase: impl FnOnce() -> Result<String, String>,
+        identities: impl FnOnce() -> Result<Vec<Box<dyn age::Identity>>, String>,
+    ) -> Result<(), String> {
         let mut file = match File::open(self.dump_file.path()) {
             Ok(file) => file,
             Err(err) => return Err(err.to_string()),
@@ -114,37 +594,153 @@ impl Mfa {
             return Err(err.to_string());
         };
 
-        self.config.deserialize(&contents)
+        self.restore_from(&contents, passphrase, identities)
+    }
+
+    // The time when the profile file is modified. None if it doesn't exist.
+    pub fn modified(&self) -> Option<SystemTime> {
+        match self.dump_file.path().metadata() {
+            Ok(meta) => meta.modified().ok(),
+            Err(_) => None,
+        }
+    }
+
+    fn restore_from(
+        &mut self,
+        contents: &str,
+        passphrase: impl FnOnce() -> Result<String, String>,
+        identities: impl FnOnce() -> Result<Vec<Box<dyn age::Identity>>, String>,
+    ) -> Result<(), String> {
+        *self.digest.borrow_mut() = Some(digest(contents.as_bytes()));
+        let (protection, plaintext) = if encryption::is_encrypted(contents) {
+            let (key, plaintext) = encryption::open(contents, &passphrase()?)?;
+            (Some(Protection::Passphrase(key)), plaintext)
+        } else if age_vault::is_vault(contents) {
+            let (vault, plaintext) = age_vault::open(contents, &identities()?)?;
+            (Some(Protection::Age(vault)), plaintext)
+        } else {
+            (None, contents.to_string())
+        };
+
+        let version = schema::version(&plaintext)?;
+        self.config.deserialize(&plaintext)?;
+        self.protection = protection;
+
+        // 古い形式のファイルは元のファイルを残してから書き換える
+        if version < schema::VERSION {
+            let backup = self.dump_file.backup_path(version);
+            if let Err(err) = storage::write_atomic(&backup, contents.as_bytes()) {
+                return Err(format!("failed to back up the profile file: {}", err));
+            }
+            self.dump()?;
+        }
+        Ok(())
     }
 
     // Run setup steps.
     //
     // Restore config if a dump file exists already.
     // Otherwise do nothing.
-    fn setup(&mut self) -> Result<(), String> {
+    fn setup(
+        &mut self,
+        passphrase: impl FnOnce() -> Result<String, String>,
+        identities: impl FnOnce() -> Result<Vec<Box<dyn age::Identity>>, String>,
+    ) -> Result<(), String> {
         // Create save dir
         if !self.dump_file.dir_exists() {
-            if let Err(err) = DirBuilder::new()
-                .recursive(true)
-                .create(self.dump_file.dir_path())
-            {
+            if let Err(err) = storage::create_private_dir(self.dump_file.dir_path()) {
                 return Err(err.to_string());
             }
         }
 
+        if self.lock.is_none() {
+            self.lock = Some(storage::Lock::acquire(&self.dump_file.lock_path())?);
+        }
+
         // nothing to do if it does not exist
         if !self.dump_file.exists() {
             return Ok(());
         }
 
         if self.dump_file.check() {
-            return self.restore();
+            check_permissions(&self.dump_file.path())?;
+            return self.restore_with(passphrase, identities);
         }
 
         Ok(())
     }
 }
 
+fn digest(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+// Refuse the profile file which other users can read.
+//
+// It only warns if MFA_CLI_PERMISSIONS is "warn".
+fn check_permissions(path: &Path) -> Result<(), String> {
+    let reason = match storage::exposure(path) {
+        Ok(Some(reason)) => reason,
+        Ok(None) => return Ok(()),
+        Err(err) => return Err(err.to_string()),
+    };
+    let message = format!(
+        "The profile file {} {}. Run `mfa-cli vault fix-permissions` to make it private.",
+        path.display(),
+        reason
+    );
+
+    match env::var(PERMISSIONS_ENV).as_deref() {
+        Ok("warn") => {
+            eprintln!("WARNING: {}", message);
+            Ok(())
+        }
+        Ok("strict") | Err(_) => Err(message),
+        Ok(_) => Err(format!("{} must be strict or warn.", PERMISSIONS_ENV)),
+    }
+}
+
+// Make the config directory, the profile file and its lock file private.
+//
+// It returns the paths which are changed.
+// It doesn't need the passphrase because the contents are not read.
+pub fn fix_permissions() -> Result<Vec<Box<Path>>, String> {
+    let dump_file: DumpFile = Default::default();
+    let paths = [
+        dump_file.dir_path().into(),
+        dump_file.path(),
+        dump_file.lock_path(),
+    ];
+
+    let mut fixed = Vec::new();
+    for path in paths {
+        let reason = match storage::exposure(&path) {
+            Ok(reason) => reason,
+            // 存在しないファイルは直すものがない
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        };
+        match reason {
+            None => {}
+            Some(reason) if reason == storage::FOREIGN_OWNER => {
+                return Err(format!(
+                    "{} {}. Change the owner by yourself.",
+                    path.display(),
+                    reason
+                ))
+            }
+            Some(_) => {
+                if let Err(err) = storage::make_private(&path) {
+                    return Err(format!("{}: {}", path.display(), err));
+                }
+                fixed.push(path);
+            }
+        }
+    }
+
+    Ok(fixed)
+}
+
 #[derive(Debug)]
 struct DumpFile {
     dir: Box<Path>,
@@ -195,11 +791,115 @@ impl DumpFile {
         path.into_boxed_path()
     }
 
+    // The backup of the profile file before it is upgraded from the version
+    fn backup_path(&self, version: u32) -> Box<Path> {
+        let file_name = format!("{}.v{}.bak", self.file_name, version);
+        self.dir.join(file_name).into_boxed_path()
+    }
+
+    fn lock_path(&self) -> Box<Path> {
+        self.dir.join(LOCK_FILE_NAME).into_boxed_path()
+    }
+
     fn dir_path(&self) -> &Path {
         &self.dir
     }
 }
 
+// Read the passphrase of the encrypted profile file.
+//
+// For automation, it is read from the file descriptor in MFA_CLI_PASSPHRASE_FD
+// or MFA_CLI_PASSPHRASE. Otherwise it is asked on the terminal.
+pub fn read_passphrase() -> Result<String, String> {
+    if let Ok(fd) = env::var(PASSPHRASE_FD_ENV) {
+        return read_passphrase_from_fd(&fd);
+    }
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    match rpassword::prompt_password("Passphrase: ") {
+        Ok(passphrase) => Ok(passphrase),
+        Err(err) => Err(format!("failed to read the passphrase: {}", err)),
+    }
+}
+
+// Read identities to open the age vault.
+//
+// The passphrase of an encrypted SSH key is asked on the terminal.
+fn read_identities() -> Result<Vec<Box<dyn age::Identity>>, String> {
+    read_identities_with(read_identity_passphrase)
+}
+
+fn read_identities_with(
+    passphrase: impl FnOnce() -> Result<String, String>,
+) -> Result<Vec<Box<dyn age::Identity>>, String> {
+    match age_vault::identity_path() {
+        Some(path) => age_vault::read_identities(&path, passphrase),
+        None => Err(String::from(
+            "The identity is not found. Set MFA_CLI_IDENTITY.",
+        )),
+    }
+}
+
+// Read the passphrase of the encrypted SSH key to open the age vault on the terminal.
+pub fn read_identity_passphrase() -> Result<String, String> {
+    let prompt = match age_vault::identity_path() {
+        Some(path) => format!("Passphrase of {}: ", path.display()),
+        None => String::from("Passphrase of the identity: "),
+    };
+    match rpassword::prompt_password(prompt) {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => Err(String::from(age_vault::IDENTITY_PASSPHRASE_REQUIRED)),
+    }
+}
+
+// 与えられたパスフレーズを返す。なければ required をエラーにする
+fn given<'a>(
+    passphrase: Option<&'a str>,
+    required: &'static str,
+) -> impl FnOnce() -> Result<String, String> + 'a {
+    move || match passphrase {
+        Some(passphrase) => Ok(passphrase.to_string()),
+        None => Err(String::from(required)),
+    }
+}
+
+// 最初の行をパスフレーズとして読む
+//
+// The file descriptor is not closed because it is owned by the caller.
+// stdout and stderr are refused because the code and errors are written to them.
+#[cfg(unix)]
+fn read_passphrase_from_fd(fd: &str) -> Result<String, String> {
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+
+    let fd = match fd.parse::<i32>() {
+        Ok(1) | Ok(2) => {
+            return Err(format!(
+                "{} must not be stdout or stderr.",
+                PASSPHRASE_FD_ENV
+            ))
+        }
+        Ok(fd) if 0 <= fd => fd,
+        _ => return Err(format!("{} must be a file descriptor.", PASSPHRASE_FD_ENV)),
+    };
+    // SAFETY: the caller passes the file descriptor to read the passphrase from.
+    // ManuallyDrop keeps it open; this process doesn't own it.
+    let file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(&*file).read_line(&mut line) {
+        return Err(format!("failed to read the passphrase: {}", err));
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+#[cfg(not(unix))]
+fn read_passphrase_from_fd(_fd: &str) -> Result<String, String> {
+    Err(format!("{} is supported only on Unix.", PASSPHRASE_FD_ENV))
+}
+
 // decides directory which dump config file
 fn fetch_dump_path() -> Box<Path> {
     if let Some(path) = env_my_home() {
@@ -256,8 +956,34 @@ fn env_home() -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::algorithm::Algorithm;
     use tempfile;
 
+    #[cfg(unix)]
+    #[test]
+    fn read_passphrase_from_fd_keeps_it_open() {
+        let mut fds = [0; 2];
+        // SAFETY: fds has room for the two file descriptors.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // SAFETY: the write end is owned by this test.
+        let mut writer = unsafe { <File as std::os::unix::io::FromRawFd>::from_raw_fd(fds[1]) };
+        writer.write_all(b"passphrase\n").unwrap();
+        drop(writer);
+
+        assert_eq!(
+            read_passphrase_from_fd(&fds[0].to_string()).unwrap(),
+            "passphrase"
+        );
+        // SAFETY: fcntl only checks whether the file descriptor is open.
+        assert_ne!(unsafe { libc::fcntl(fds[0], libc::F_GETFD) }, -1);
+        // SAFETY: the read end is owned by this test.
+        unsafe { libc::close(fds[0]) };
+
+        assert!(read_passphrase_from_fd("1").is_err());
+        assert!(read_passphrase_from_fd("2").is_err());
+        assert!(read_passphrase_from_fd("-1").is_err());
+    }
+
     #[test]
     fn dump_file_path() {
         let dump_file = DumpFile {
@@ -370,6 +1096,346 @@ mod tests {
         assert!(mfa.get_secret_by_name("test").is_none());
     }
 
+    #[test]
+    fn test_add_profile() {
+        let mut mfa: Mfa = Default::default();
+        let mut profile = config::Profile::new("test", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        profile.set_algorithm(Algorithm::Sha256);
+
+        mfa.add_profile(profile).unwrap();
+        assert!(mfa.get_code_by_name("test").is_ok());
+    }
+
+    #[test]
+    fn test_get_code_by_name_with_digits() {
+        let mut mfa: Mfa = Default::default();
+        let mut profile = config::Profile::new("test", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        profile.set_digits(8);
+        profile.set_period(60);
+
+        mfa.add_profile(profile).unwrap();
+        assert_eq!(mfa.get_code_by_name("test").unwrap().len(), 8);
+    }
+
+    #[test]
+    fn test_consume_code_by_name_with_hotp() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut mfa = Mfa {
+            config: Default::default(),
+            dump_file: DumpFile {
+                dir: dir.path().to_path_buf().into_boxed_path(),
+                file_name: CONFIG_FILE_NAME,
+            },
+            protection: None,
+            lock: None,
+            digest: Default::default(),
+        };
+        // base32 of "12345678901234567890"
+        let mut profile = config::Profile::new("test", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        profile.set_type(ProfileType::Hotp);
+        mfa.add_profile(profile).unwrap();
+
+        assert_eq!(mfa.get_code_by_name("test").unwrap(), "755224");
+        assert_eq!(mfa.consume_code_by_name("test").unwrap(), "755224");
+        assert_eq!(mfa.consume_code_by_name("test").unwrap(), "287082");
+        assert_eq!(mfa.get_counter("test"), Ok(2));
+
+        let dumped = std::fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap();
+        assert!(dumped.contains("counter = 2"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_setup_refuses_public_profile_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        std::fs::write(
+            &path,
+            "[[profiles]]\nname = \"test\"\nsecret = \"JBSWY3DPEHPK3PXP\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut mfa = Mfa {
+            dump_file: DumpFile {
+                dir: dir.path().to_path_buf().into_boxed_path(),
+                file_name: CONFIG_FILE_NAME,
+            },
+            ..Default::default()
+        };
+        let err = mfa
+            .setup(|| Err(String::from("unused")), || Ok(vec![]))
+            .unwrap_err();
+        assert!(err.contains("is accessible by group or other users"));
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        mfa.setup(|| Err(String::from("unused")), || Ok(vec![]))
+            .unwrap();
+        assert!(mfa.get_secret_by_name("test").is_some());
+    }
+
+    #[test]
+    fn test_restore_upgrades_old_profile_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = "[[profiles]]\nname = \"test\"\nsecret = \"JBSWY3DPEHPK3PXP\"\n";
+        let mut mfa = Mfa {
+            dump_file: DumpFile {
+                dir: dir.path().to_path_buf().into_boxed_path(),
+                file_name: CONFIG_FILE_NAME,
+            },
+            ..Default::default()
+        };
+        std::fs::write(dir.path().join(CONFIG_FILE_NAME), old).unwrap();
+        mfa.restore_from(old, || Err(String::from("unused")), || Ok(vec![]))
+            .unwrap();
+
+        assert!(mfa.get_secret_by_name("test").is_some());
+        let backup = std::fs::read_to_string(dir.path().join("profile.v0.bak")).unwrap();
+        assert_eq!(backup, old);
+        let dumped = std::fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap();
+        assert_eq!(schema::version(&dumped), Ok(schema::VERSION));
+
+        let newer = format!("version = {}\n{}", schema::VERSION + 1, old);
+        assert!(mfa
+            .restore_from(&newer, || Err(String::from("unused")), || Ok(vec![]))
+            .unwrap_err()
+            .contains("newer mfa-cli"));
+    }
+
+    #[test]
+    fn test_parallel_read_modify_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump_file = || DumpFile {
+            dir: dir.path().to_path_buf().into_boxed_path(),
+            file_name: CONFIG_FILE_NAME,
+        };
+
+        std::thread::scope(|scope| {
+            for name in ["first", "second", "third", "fourth"] {
+                scope.spawn(move || {
+                    let mut mfa = Mfa {
+                        dump_file: dump_file(),
+                        ..Default::default()
+                    };
+                    mfa.setup(|| Err(String::from("unused")), || Ok(vec![]))
+                        .unwrap();
+                    mfa.register_profile(name, "JBSWY3DPEHPK3PXP").unwrap();
+                    mfa.dump().unwrap();
+                });
+            }
+        });
+
+        let mut restored = Mfa {
+            dump_file: dump_file(),
+            ..Default::default()
+        };
+        restored
+            .setup(|| Err(String::from("unused")), || Ok(vec![]))
+            .unwrap();
+        assert_eq!(restored.list_profiles().len(), 4);
+    }
+
+    #[test]
+    fn test_dump_and_restore_encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump_file = || DumpFile {
+            dir: dir.path().to_path_buf().into_boxed_path(),
+            file_name: CONFIG_FILE_NAME,
+        };
+        let mut mfa = Mfa {
+            config: Default::default(),
+            dump_file: dump_file(),
+            protection: Some(Protection::Passphrase(
+                encryption::Key::with_params("passphrase", encryption::tests::TEST_PARAMS).unwrap(),
+            )),
+            lock: None,
+            digest: Default::default(),
+        };
+        mfa.config.new_profile("test", "JBSWY3DPEHPK3PXP").unwrap();
+        mfa.dump().unwrap();
+
+        let contents = std::fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap();
+        assert!(!contents.contains("JBSWY3DPEHPK3PXP"));
+
+        let mut restored = Mfa {
+            config: Default::default(),
+            dump_file: dump_file(),
+            protection: None,
+            lock: None,
+            digest: Default::default(),
+        };
+        assert!(restored
+            .restore_from(&contents, || Ok(String::from("wrong")), || Ok(vec![]))
+            .is_err());
+        restored
+            .restore_from(&contents, || Ok(String::from("passphrase")), || Ok(vec![]))
+            .unwrap();
+        assert!(restored.is_encrypted());
+        assert!(restored.get_secret_by_name("test").is_some());
+
+        restored.decrypt().unwrap();
+        restored.dump().unwrap();
+        let contents = std::fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap();
+        assert!(contents.contains("JBSWY3DPEHPK3PXP"));
+    }
+
+    #[test]
+    fn test_dump_and_restore_age_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump_file = || DumpFile {
+            dir: dir.path().to_path_buf().into_boxed_path(),
+            file_name: CONFIG_FILE_NAME,
+        };
+        let recipient = "age1y8fh898jhd4h2csd8lkx3leurre25a7pxfk497c7765e7jqxud7qjdate6";
+        let mut mfa = Mfa {
+            config: Default::default(),
+            dump_file: dump_file(),
+            protection: None,
+            lock: None,
+            digest: Default::default(),
+        };
+        mfa.config.new_profile("test", "JBSWY3DPEHPK3PXP").unwrap();
+        mfa.encrypt_to_recipients(&[recipient.to_string()]).unwrap();
+        assert!(mfa.change_passphrase("passphrase").is_err());
+        mfa.dump().unwrap();
+
+        let contents = std::fs::read_to_string(dir.path().join(CONFIG_FILE_NAME)).unwrap();
+        let mut restored = Mfa {
+            config: Default::default(),
+            dump_file: dump_file(),
+            protection: None,
+            lock: None,
+            digest: Default::default(),
+        };
+        restored
+            .restore_from(
+                &contents,
+                || Err(String::from("unused")),
+                || {
+                    age_vault::read_identities(Path::new("tests/fixtures/age-identity.txt"), || {
+                        Err(String::from("unused"))
+                    })
+                },
+            )
+            .unwrap();
+        assert_eq!(restored.get_recipients(), Ok(&vec![recipient.to_string()]));
+        assert!(restored.get_secret_by_name("test").is_some());
+    }
+
+    #[test]
+    fn test_set_counter_to_totp_profile() {
+        let mut mfa: Mfa = Default::default();
+        mfa.config.new_profile("test", "hoge").unwrap();
+
+        assert!(mfa.set_counter("test", 1).is_err());
+    }
+
+    #[test]
+    fn test_get_profiles_by_filter() {
+        let mut mfa: Mfa = Default::default();
+        mfa.config.new_profile("test1", "hoge").unwrap();
+        mfa.config.new_profile("test2", "hoge").unwrap();
+        mfa.config
+            .find_by_name_mut("test2")
+            .unwrap()
+            .add_tag("work");
+        let work = Filter::new(&["work".to_string()], None, None);
+
+        let profiles = mfa.get_profiles_by_filter(&[], &work).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].get_name(), "test2");
+        let profiles = mfa
+            .get_profiles_by_filter(&["test1".to_string()], &work)
+            .unwrap();
+        assert!(profiles.is_empty());
+
+        assert_eq!(
+            mfa.resolve_profile_name("test2", &work, true).unwrap(),
+            "test2"
+        );
+        assert!(mfa.resolve_profile_name("test1", &work, true).is_err());
+        assert_eq!(
+            mfa.resolve_profile_name("test1", &Default::default(), true)
+                .unwrap(),
+            "test1"
+        );
+    }
+
+    #[test]
+    fn test_resolve_profile_name_by_prefix_and_fuzzy() {
+        let mut mfa: Mfa = Default::default();
+        mfa.config.new_profile("github-work", "hoge").unwrap();
+        mfa.config.new_profile("gitlab", "hoge").unwrap();
+        mfa.config.new_profile("bank", "hoge").unwrap();
+        mfa.config.new_profile("web-bank", "hoge").unwrap();
+        let resolve =
+            |name: &str, exact: bool| mfa.resolve_profile_name(name, &Default::default(), exact);
+
+        assert_eq!(resolve("gh", false).unwrap(), "github-work");
+        assert_eq!(resolve("gitl", false).unwrap(), "gitlab");
+        assert_eq!(
+            resolve("git", false).unwrap_err(),
+            "the profile name is ambiguous: git\n  gitlab\n  github-work"
+        );
+        // 前方一致があれば曖昧一致の候補は含めない
+        assert_eq!(resolve("BA", false).unwrap(), "bank");
+        assert_eq!(
+            resolve("bnk", false).unwrap_err(),
+            "the profile name is ambiguous: bnk\n  bank\n  web-bank"
+        );
+        assert!(resolve("xyz", false).is_err());
+        assert!(resolve("gh", true).is_err());
+        assert_eq!(resolve("gitlab", true).unwrap(), "gitlab");
+    }
+
+    #[test]
+    fn test_get_profiles_by_names() {
+        let mut mfa: Mfa = Default::default();
+        mfa.config.new_profile("test1", "hoge").unwrap();
+        mfa.config.new_profile("test2", "hoge").unwrap();
+
+        assert_eq!(mfa.get_profiles_by_names(&[]).unwrap().len(), 2);
+
+        let profiles = mfa.get_profiles_by_names(&["test2".to_string()]).unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles[0].get_name(), "test2");
+
+        assert!(mfa.get_profiles_by_names(&["test3".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_import_profiles() {
+        let mut mfa: Mfa = Default::default();
+        mfa.config.new_profile("test1", "hoge").unwrap();
+
+        let report = mfa.import_profiles(vec![
+            Ok(config::Profile::new("test1", "hoge")),
+            Ok(config::Profile::new("test1", "fuga")),
+            Ok(config::Profile::new("test2", "hoge")),
+            Ok(config::Profile::new("x", "hoge")),
+            Err(import::Skipped::new("qr.png", "broken")),
+        ]);
+
+        assert_eq!(
+            report.get_imported(),
+            &vec!["test1-2".to_string(), "test2".to_string()]
+        );
+        assert_eq!(
+            report.get_skipped(),
+            &vec![
+                import::Skipped::new("test1", "The same profile already exists."),
+                import::Skipped::new("x", "Name requires at least 3 characters."),
+                import::Skipped::new("qr.png", "broken"),
+            ]
+        );
+        assert_eq!(
+            mfa.get_secret_by_name("test1-2"),
+            base32::decode(base32::Alphabet::RFC4648 { padding: true }, "fuga")
+        );
+    }
+
     #[test]
     fn test_list_profiles() {
         let mut mfa: Mfa = Default::default();
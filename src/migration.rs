@@ -0,0 +1,615 @@
+extern crate base64;
+extern crate percent_encoding;
+
+use super::algorithm::Algorithm;
+use super::config::{Profile, ProfileType, ValidationError};
+use super::import;
+use super::totp;
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use std::time::SystemTime;
+
+type ParseResult<T> = Result<T, ValidationError>;
+
+const SCHEME: &str = "otpauth-migration://offline?";
+
+// Google Authenticator が出力する payload の version
+const VERSION: u64 = 1;
+
+// Google Authenticator は padding の有無が揺れるのでどちらも受け付ける
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+// Google Authenticator の "Transfer accounts" で出力される
+// otpauth-migration://offline?data=BASE64 の中身
+//
+// message MigrationPayload {
+//   repeated OtpParameters otp_parameters = 1;
+//   int32 version = 2;
+//   int32 batch_size = 3;
+//   int32 batch_index = 4;
+//   int32 batch_id = 5;
+// }
+#[derive(Debug, Default, PartialEq)]
+pub struct Payload {
+    entries: Vec<Entry>,
+    version: u64,
+    batch_size: u64,
+    batch_index: u64,
+    batch_id: u64,
+}
+
+// message OtpParameters {
+//   bytes secret = 1;
+//   string name = 2;
+//   string issuer = 3;
+//   Algorithm algorithm = 4;  // 0: unspecified, 1: SHA1, 2: SHA256, 3: SHA512, 4: MD5
+//   DigitCount digits = 5;    // 0: unspecified, 1: six, 2: eight
+//   OtpType type = 6;         // 0: unspecified, 1: HOTP, 2: TOTP
+//   int64 counter = 7;
+// }
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Entry {
+    secret: Vec<u8>,
+    name: String,
+    issuer: String,
+    algorithm: u64,
+    digits: u64,
+    otp_type: u64,
+    counter: u64,
+}
+
+impl Payload {
+    // Parse a otpauth-migration URI.
+    pub fn parse(uri: &str) -> ParseResult<Self> {
+        let query = match uri.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+            _ => {
+                return Err(ValidationError::InvalidFormat(
+                    "URI must start with otpauth-migration://offline.",
+                ))
+            }
+        };
+
+        let data = match query.split('&').find_map(|pair| pair.strip_prefix("data=")) {
+            Some(data) => data,
+            None => return Err(ValidationError::Requires("Data must be present.")),
+        };
+
+        let data = match percent_decode_str(data).decode_utf8() {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(ValidationError::InvalidFormat(
+                    "URI must be encoded in UTF-8.",
+                ))
+            }
+        };
+        let bytes = match BASE64.decode(data.trim().as_bytes()) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                return Err(ValidationError::InvalidFormat(
+                    "Data must be encoded in base64.",
+                ))
+            }
+        };
+
+        Self::decode(&bytes)
+    }
+
+    // Build URIs from profiles.
+    //
+    // Profiles are split into batches which contain batch_size profiles at most.
+    // All of batches share a batch_id so that they are imported as one export.
+    pub fn export(profiles: &[&Profile], batch_size: usize) -> ParseResult<Vec<String>> {
+        if batch_size == 0 {
+            return Err(ValidationError::OutOfRange(
+                "Batch size requires at least 1.",
+            ));
+        }
+
+        let entries = profiles
+            .iter()
+            .map(|profile| Entry::from_profile(profile))
+            .collect::<ParseResult<Vec<Entry>>>()?;
+
+        let batch_id = generate_batch_id();
+        let chunks: Vec<&[Entry]> = entries.chunks(batch_size).collect();
+        let batches = chunks.len() as u64;
+
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let payload = Self {
+                    entries: chunk.to_vec(),
+                    version: VERSION,
+                    batch_size: batches,
+                    batch_index: index as u64,
+                    batch_id,
+                };
+                payload.to_uri()
+            })
+            .collect())
+    }
+
+    // Check whether the profile can be exported.
+    pub fn validate_profile(profile: &Profile) -> ParseResult<()> {
+        Entry::from_profile(profile).map(|_| ())
+    }
+
+    // Format as a otpauth-migration URI.
+    pub fn to_uri(&self) -> String {
+        let data = BASE64.encode(self.encode());
+        format!(
+            "{}data={}",
+            SCHEME,
+            utf8_percent_encode(&data, NON_ALPHANUMERIC)
+        )
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut writer: Writer = Default::default();
+
+        for entry in &self.entries {
+            writer.write_bytes(1, &entry.encode());
+        }
+        writer.write_varint(2, self.version);
+        writer.write_varint(3, self.batch_size);
+        writer.write_varint(4, self.batch_index);
+        writer.write_varint(5, self.batch_id);
+
+        writer.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> ParseResult<Self> {
+        let mut this: Self = Default::default();
+        let mut reader = Reader::new(bytes);
+
+        while let Some((field, wire_type)) = reader.read_key()? {
+            match (field, wire_type) {
+                (1, WIRE_LEN) => this.entries.push(Entry::decode(reader.read_bytes()?)?),
+                (2, WIRE_VARINT) => this.version = reader.read_varint()?,
+                (3, WIRE_VARINT) => this.batch_size = reader.read_varint()?,
+                (4, WIRE_VARINT) => this.batch_index = reader.read_varint()?,
+                (5, WIRE_VARINT) => this.batch_id = reader.read_varint()?,
+                _ => reader.skip(wire_type)?,
+            }
+        }
+
+        Ok(this)
+    }
+
+    pub fn get_entries(&self) -> &Vec<Entry> {
+        &self.entries
+    }
+
+    // The number of URIs of the export.
+    pub fn get_batch_size(&self) -> u64 {
+        self.batch_size
+    }
+
+    // The position of this URI in the export. It starts at 0.
+    pub fn get_batch_index(&self) -> u64 {
+        self.batch_index
+    }
+
+    // The ID which is common in all of URIs of the export.
+    pub fn get_batch_id(&self) -> u64 {
+        self.batch_id
+    }
+}
+
+impl Entry {
+    // Build from a profile.
+    //
+    // Google Authenticator supports only 6 or 8 digits, 30 seconds period and T0 = 0.
+    // The account is used as the name. The profile name is used if it is unknown.
+    fn from_profile(profile: &Profile) -> ParseResult<Self> {
+        let secret = match profile.get_secret() {
+            Some(secret) => secret,
+            None => {
+                return Err(ValidationError::InvalidFormat(
+                    "Secret must be encoded in base32.",
+                ))
+            }
+        };
+        let digits = match profile.get_digits() {
+            6 => 1,
+            8 => 2,
+            _ => return Err(ValidationError::Unsupported("Digits must be 6 or 8.")),
+        };
+        let otp_type = match profile.get_type() {
+            ProfileType::Hotp => 1,
+            ProfileType::Totp => {
+                if profile.get_period() != totp::TIME_STEP || profile.get_t0() != 0 {
+                    return Err(ValidationError::Unsupported(
+                        "Period must be 30 seconds and T0 must be 0.",
+                    ));
+                }
+                2
+            }
+            ProfileType::Steam => {
+                return Err(ValidationError::Unsupported(
+                    "Steam Guard is not supported.",
+                ))
+            }
+        };
+        let algorithm = match profile.get_algorithm() {
+            Algorithm::Sha1 => 1,
+            Algorithm::Sha256 => 2,
+            Algorithm::Sha512 => 3,
+        };
+
+        Ok(Self {
+            secret,
+            name: profile
+                .get_account()
+                .unwrap_or(profile.get_name())
+                .to_string(),
+            issuer: profile.get_issuer().unwrap_or_default().to_string(),
+            algorithm,
+            digits,
+            otp_type,
+            counter: profile.get_counter(),
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut writer: Writer = Default::default();
+
+        writer.write_bytes(1, &self.secret);
+        writer.write_bytes(2, self.name.as_bytes());
+        if !self.issuer.is_empty() {
+            writer.write_bytes(3, self.issuer.as_bytes());
+        }
+        writer.write_varint(4, self.algorithm);
+        writer.write_varint(5, self.digits);
+        writer.write_varint(6, self.otp_type);
+        if self.counter != 0 {
+            writer.write_varint(7, self.counter);
+        }
+
+        writer.into_bytes()
+    }
+
+    fn decode(bytes: &[u8]) -> ParseResult<Self> {
+        let mut this: Self = Default::default();
+        let mut reader = Reader::new(bytes);
+
+        while let Some((field, wire_type)) = reader.read_key()? {
+            match (field, wire_type) {
+                (1, WIRE_LEN) => this.secret = reader.read_bytes()?.to_vec(),
+                (2, WIRE_LEN) => this.name = reader.read_string()?,
+                (3, WIRE_LEN) => this.issuer = reader.read_string()?,
+                (4, WIRE_VARINT) => this.algorithm = reader.read_varint()?,
+                (5, WIRE_VARINT) => this.digits = reader.read_varint()?,
+                (6, WIRE_VARINT) => this.otp_type = reader.read_varint()?,
+                (7, WIRE_VARINT) => this.counter = reader.read_varint()?,
+                _ => reader.skip(wire_type)?,
+            }
+        }
+
+        Ok(this)
+    }
+}
+
+impl import::Entry for Entry {
+    // A label to show the entry without the secret. "Issuer:Account" or "Account"
+    fn label(&self) -> String {
+        if self.issuer.is_empty() || self.name.starts_with(&format!("{}:", self.issuer)) {
+            return self.name.clone();
+        }
+
+        format!("{}:{}", self.issuer, self.name)
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the issuer and the account like otpauth URI.
+    fn to_profile(&self) -> ParseResult<Profile> {
+        let algorithm = match self.algorithm {
+            0 | 1 => Algorithm::Sha1,
+            2 => Algorithm::Sha256,
+            3 => Algorithm::Sha512,
+            _ => {
+                return Err(ValidationError::Unsupported(
+                    "Algorithm must be SHA1, SHA256 or SHA512.",
+                ))
+            }
+        };
+        let digits = match self.digits {
+            0 | 1 => 6,
+            2 => 8,
+            _ => return Err(ValidationError::Unsupported("Digits must be 6 or 8.")),
+        };
+        let profile_type = match self.otp_type {
+            0 | 2 => ProfileType::Totp,
+            1 => ProfileType::Hotp,
+            _ => return Err(ValidationError::Unsupported("Type must be totp or hotp.")),
+        };
+        if self.secret.is_empty() {
+            return Err(ValidationError::Requires("Secret must be present."));
+        }
+
+        let account = match self.name.split_once(':') {
+            Some((_, account)) => account.trim(),
+            None => self.name.trim(),
+        };
+        let name = import::profile_name(&self.issuer, account);
+        let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &self.secret);
+
+        let mut profile = Profile::new(&name, &secret);
+        profile.set_issuer(&self.issuer);
+        profile.set_account(account);
+        profile.set_type(profile_type);
+        profile.set_algorithm(algorithm);
+        profile.set_digits(digits);
+        profile.set_counter(self.counter);
+        Ok(profile)
+    }
+}
+
+// Protocol Buffers の wire type
+const WIRE_VARINT: u64 = 0;
+const WIRE_I64: u64 = 1;
+const WIRE_LEN: u64 = 2;
+const WIRE_I32: u64 = 5;
+
+const BROKEN_DATA: ValidationError = ValidationError::InvalidFormat("Migration data is broken.");
+
+// Protocol Buffers の最小限のデコーダー
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    // Read a field number and a wire type. It returns None at the end.
+    fn read_key(&mut self) -> ParseResult<Option<(u64, u64)>> {
+        if self.bytes.len() <= self.position {
+            return Ok(None);
+        }
+
+        let key = self.read_varint()?;
+        Ok(Some((key >> 3, key & 0x07)))
+    }
+
+    fn read_varint(&mut self) -> ParseResult<u64> {
+        let mut value: u64 = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = match self.bytes.get(self.position) {
+                Some(byte) => *byte,
+                None => return Err(BROKEN_DATA),
+            };
+            self.position += 1;
+
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(BROKEN_DATA)
+    }
+
+    fn read_bytes(&mut self) -> ParseResult<&'a [u8]> {
+        let length = self.read_varint()? as usize;
+        self.take(length)
+    }
+
+    fn read_string(&mut self) -> ParseResult<String> {
+        match String::from_utf8(self.read_bytes()?.to_vec()) {
+            Ok(string) => Ok(string),
+            Err(_) => Err(BROKEN_DATA),
+        }
+    }
+
+    fn take(&mut self, length: usize) -> ParseResult<&'a [u8]> {
+        let end = match self.position.checked_add(length) {
+            Some(end) if end <= self.bytes.len() => end,
+            _ => return Err(BROKEN_DATA),
+        };
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    // Skip a field which is not used.
+    fn skip(&mut self, wire_type: u64) -> ParseResult<()> {
+        match wire_type {
+            WIRE_VARINT => self.read_varint().map(|_| ()),
+            WIRE_I64 => self.take(8).map(|_| ()),
+            WIRE_LEN => self.read_bytes().map(|_| ()),
+            WIRE_I32 => self.take(4).map(|_| ()),
+            _ => Err(BROKEN_DATA),
+        }
+    }
+}
+
+// Protocol Buffers の最小限のエンコーダー
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn write_varint(&mut self, field: u64, value: u64) {
+        self.push_varint(field << 3 | WIRE_VARINT);
+        self.push_varint(value);
+    }
+
+    fn write_bytes(&mut self, field: u64, value: &[u8]) {
+        self.push_varint(field << 3 | WIRE_LEN);
+        self.push_varint(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn push_varint(&mut self, value: u64) {
+        let mut value = value;
+
+        while 0x80 <= value {
+            self.bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// Generate a positive int32 ID from the current time.
+fn generate_batch_id() -> u64 {
+    let nanos = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(n) => n.subsec_nanos() as u64 ^ n.as_secs(),
+        Err(_) => 0,
+    };
+
+    nanos & 0x7fff_ffff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    // 2 entries
+    //   - secret: "Hello!\xde\xad\xbe\xef", name: "Example:alice@google.com", issuer: "Example",
+    //     algorithm: SHA1, digits: six, type: TOTP
+    //   - secret: "12345678901234567890", name: "bob", algorithm: SHA256, digits: eight, type: HOTP,
+    //     counter: 5
+    // batch_size: 1, batch_index: 0, batch_id: 12345
+    const URI: &str = "otpauth-migration://offline?data=CjUKCkhlbGxvId6tvu8SGEV4YW1wbGU6YWxpY2VAZ29vZ2xlLmNvbRoHRXhhbXBsZSABKAEwAgojChQxMjM0NTY3ODkwMTIzNDU2Nzg5MBIDYm9iIAIoAjABOAUQARgBIAAouWA%3D";
+
+    #[test]
+    fn parse_migration_uri() {
+        let payload = Payload::parse(URI).unwrap();
+
+        assert_eq!(payload.entries.len(), 2);
+        assert_eq!(payload.version, 1);
+        assert_eq!(payload.batch_size, 1);
+        assert_eq!(payload.batch_index, 0);
+        assert_eq!(payload.batch_id, 12345);
+
+        assert_eq!(payload.entries[0].secret, b"Hello!\xde\xad\xbe\xef");
+        assert_eq!(payload.entries[0].name, "Example:alice@google.com");
+        assert_eq!(payload.entries[0].issuer, "Example");
+        assert_eq!(payload.entries[1].counter, 5);
+    }
+
+    #[test]
+    fn entry_to_totp_profile() {
+        let payload = Payload::parse(URI).unwrap();
+        let profile = payload.entries[0].to_profile().unwrap();
+
+        let mut expected = Profile::new("Example-alice@google", "JBSWY3DPEHPK3PXP");
+        expected.set_issuer("Example");
+        expected.set_account("alice@google.com");
+        expected.set_type(ProfileType::Totp);
+        assert_eq!(profile, expected);
+        assert_eq!(payload.entries[0].label(), "Example:alice@google.com");
+    }
+
+    #[test]
+    fn entry_to_hotp_profile() {
+        let payload = Payload::parse(URI).unwrap();
+        let profile = payload.entries[1].to_profile().unwrap();
+
+        assert_eq!(profile.get_name(), "bob");
+        assert_eq!(profile.get_type(), ProfileType::Hotp);
+        assert_eq!(profile.get_algorithm(), Algorithm::Sha256);
+        assert_eq!(profile.get_digits(), 8);
+        assert_eq!(profile.get_counter(), 5);
+    }
+
+    #[test]
+    fn entry_with_md5() {
+        let uri = "otpauth-migration://offline?data=CiEKFDEyMzQ1Njc4OTAxMjM0NTY3ODkwEgNtZDUgBCgBMAIQARgBIAAoAQ%3D%3D";
+        let payload = Payload::parse(uri).unwrap();
+
+        assert_eq!(
+            payload.entries[0].to_profile(),
+            Err(ValidationError::Unsupported(
+                "Algorithm must be SHA1, SHA256 or SHA512."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_broken_data() {
+        let uri = "otpauth-migration://offline?data=CjUKCkhlbGxv";
+
+        assert_eq!(Payload::parse(uri), Err(BROKEN_DATA));
+    }
+
+    #[test]
+    fn parse_uri_without_data() {
+        assert_eq!(
+            Payload::parse("otpauth-migration://offline?foo=bar"),
+            Err(ValidationError::Requires("Data must be present."))
+        );
+    }
+
+    #[test]
+    fn parse_otpauth_uri() {
+        assert!(Payload::parse("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP").is_err());
+    }
+
+    #[test]
+    fn encode_same_bytes_as_decoded() {
+        let payload = Payload::parse(URI).unwrap();
+
+        assert_eq!(payload.to_uri(), URI);
+    }
+
+    #[test]
+    fn export_profiles_in_batches() {
+        let mut first = Profile::new("Example-first", "JBSWY3DPEHPK3PXP");
+        first.set_issuer("Example");
+        first.set_account("first");
+        let mut hotp = Profile::new("hotp", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        hotp.set_account("hotp");
+        hotp.set_type(ProfileType::Hotp);
+        hotp.set_counter(3);
+        let profiles = [first, Profile::new("second", "JBSWY3DPEHPK3PXP"), hotp];
+        let profiles: Vec<&Profile> = profiles.iter().collect();
+
+        let uris = Payload::export(&profiles, 2).unwrap();
+        assert_eq!(uris.len(), 2);
+
+        let first = Payload::parse(&uris[0]).unwrap();
+        let second = Payload::parse(&uris[1]).unwrap();
+        assert_eq!(first.entries.len(), 2);
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!((first.batch_size, first.batch_index), (2, 0));
+        assert_eq!((second.batch_size, second.batch_index), (2, 1));
+        assert_eq!(first.batch_id, second.batch_id);
+
+        assert_eq!(first.entries[0].to_profile().unwrap(), *profiles[0]);
+        assert_eq!(second.entries[0].to_profile().unwrap(), *profiles[2]);
+    }
+
+    #[test]
+    fn export_profile_with_unsupported_period() {
+        let mut profile = Profile::new("test", "JBSWY3DPEHPK3PXP");
+        profile.set_period(60);
+
+        assert_eq!(
+            Payload::export(&[&profile], 10),
+            Err(ValidationError::Unsupported(
+                "Period must be 30 seconds and T0 must be 0."
+            ))
+        );
+    }
+}
@@ -0,0 +1,562 @@
+extern crate percent_encoding;
+
+use super::algorithm::Algorithm;
+use super::config::{Profile, ProfileType, ValidationError};
+use super::totp;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fmt;
+
+type ParseResult<T> = Result<T, ValidationError>;
+
+const SCHEME: &str = "otpauth://";
+
+// Characters that are encoded in URI. Unreserved characters (RFC 3986) are kept.
+const ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Key Uri Format
+//
+// otpauth://TYPE/LABEL?PARAMETERS
+// https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+#[derive(Debug, PartialEq)]
+pub struct KeyUri {
+    profile_type: ProfileType,
+    issuer: Option<String>,
+    account: String,
+    secret: String,
+    algorithm: Algorithm,
+    digits: u8,
+    period: u64,
+    counter: u64,
+}
+
+impl KeyUri {
+    // Parse a otpauth URI.
+    pub fn parse(uri: &str) -> ParseResult<Self> {
+        let rest = match uri.get(..SCHEME.len()) {
+            Some(scheme) if scheme.eq_ignore_ascii_case(SCHEME) => &uri[SCHEME.len()..],
+            _ => {
+                return Err(ValidationError::InvalidFormat(
+                    "URI must start with otpauth://.",
+                ))
+            }
+        };
+
+        let (profile_type, rest) = match rest.split_once('/') {
+            Some((profile_type, rest)) => match profile_type.parse::<ProfileType>() {
+                Ok(ProfileType::Steam) | Err(_) => {
+                    return Err(ValidationError::Unsupported("Type must be totp or hotp."))
+                }
+                Ok(profile_type) => (profile_type, rest),
+            },
+            None => return Err(ValidationError::Requires("Label must be present.")),
+        };
+
+        let (label, query) = match rest.split_once('?') {
+            Some((label, query)) => (label, query),
+            None => (rest, ""),
+        };
+
+        let mut this = Self {
+            profile_type,
+            issuer: None,
+            account: String::new(),
+            secret: String::new(),
+            algorithm: Default::default(),
+            digits: totp::TOTP_DIGITS,
+            period: totp::TIME_STEP,
+            counter: 0,
+        };
+        this.parse_label(label)?;
+        this.parse_query(query)?;
+
+        Ok(this)
+    }
+
+    // Build from a profile.
+    //
+    // The profile name is used as the account if the profile doesn't know the account.
+    // A TOTP profile with non-zero T0 can't be exported because Key Uri Format doesn't have it.
+    pub fn from_profile(profile: &Profile) -> ParseResult<Self> {
+        if profile.get_type() == ProfileType::Steam {
+            return Err(ValidationError::Unsupported("Type must be totp or hotp."));
+        }
+        if profile.get_type() == ProfileType::Totp && profile.get_t0() != 0 {
+            return Err(ValidationError::Unsupported("T0 must be 0."));
+        }
+        let secret = match profile.get_secret() {
+            Some(secret) => base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret),
+            None => {
+                return Err(ValidationError::InvalidFormat(
+                    "Secret must be encoded in base32.",
+                ))
+            }
+        };
+
+        Ok(Self {
+            profile_type: profile.get_type(),
+            issuer: profile.get_issuer().map(String::from),
+            account: profile
+                .get_account()
+                .unwrap_or(profile.get_name())
+                .to_string(),
+            secret,
+            algorithm: profile.get_algorithm(),
+            digits: profile.get_digits(),
+            period: profile.get_period(),
+            counter: profile.get_counter(),
+        })
+    }
+
+    // Parse the label part. "Issuer:Account" or "Account"
+    fn parse_label(&mut self, label: &str) -> ParseResult<()> {
+        let label = decode(label, false)?;
+
+        let (issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim()), account.trim()),
+            None => (None, label.trim()),
+        };
+
+        if account.is_empty() {
+            return Err(ValidationError::Requires("Label must be present."));
+        }
+
+        self.issuer = issuer.filter(|issuer| !issuer.is_empty()).map(String::from);
+        self.account = account.to_string();
+
+        Ok(())
+    }
+
+    // Parse the parameters part.
+    fn parse_query(&mut self, query: &str) -> ParseResult<()> {
+        let mut seen: Vec<String> = Vec::new();
+        let mut has_counter = false;
+
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key.to_lowercase(), decode(value, true)?),
+                None => (pair.to_lowercase(), String::new()),
+            };
+
+            if seen.contains(&key) {
+                return Err(ValidationError::InvalidFormat(
+                    "URI contains a duplicated parameter.",
+                ));
+            }
+
+            match key.as_str() {
+                "secret" => self.secret = normalize_secret(&value)?,
+                "issuer" => self.merge_issuer(value)?,
+                "algorithm" => {
+                    self.algorithm = match value.parse() {
+                        Ok(algorithm) => algorithm,
+                        Err(_) => {
+                            return Err(ValidationError::Unsupported(
+                                "Algorithm must be SHA1, SHA256 or SHA512.",
+                            ))
+                        }
+                    }
+                }
+                "digits" => {
+                    self.digits = match value.parse() {
+                        Ok(digits) => digits,
+                        Err(_) => {
+                            return Err(ValidationError::InvalidFormat("Digits must be a number."))
+                        }
+                    }
+                }
+                "period" => {
+                    self.period = match value.parse() {
+                        Ok(period) => period,
+                        Err(_) => {
+                            return Err(ValidationError::InvalidFormat("Period must be a number."))
+                        }
+                    }
+                }
+                "counter" => {
+                    self.counter = match value.parse() {
+                        Ok(counter) => counter,
+                        Err(_) => {
+                            return Err(ValidationError::InvalidFormat("Counter must be a number."))
+                        }
+                    };
+                    has_counter = true;
+                }
+                // An image for the account that some providers append. It is not used.
+                "image" => {}
+                _ => {
+                    return Err(ValidationError::Unsupported(
+                        "URI contains an unsupported parameter.",
+                    ))
+                }
+            }
+
+            seen.push(key);
+        }
+
+        if self.secret.is_empty() {
+            return Err(ValidationError::Requires("Secret must be present."));
+        }
+        if self.profile_type == ProfileType::Hotp && !has_counter {
+            return Err(ValidationError::Requires(
+                "Counter must be present for hotp.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    // The issuer parameter must be equal to the issuer prefix of the label if both are present.
+    fn merge_issuer(&mut self, issuer: String) -> ParseResult<()> {
+        let issuer = issuer.trim().to_string();
+        if issuer.is_empty() {
+            return Ok(());
+        }
+
+        match &self.issuer {
+            Some(label_issuer) if *label_issuer != issuer => Err(ValidationError::InvalidFormat(
+                "Issuer of the label and the parameter must be the same.",
+            )),
+            _ => {
+                self.issuer = Some(issuer);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn get_issuer(&self) -> Option<&String> {
+        self.issuer.as_ref()
+    }
+
+    pub fn get_account(&self) -> &String {
+        &self.account
+    }
+
+    // Derive a profile name from the issuer and the account.
+    //
+    // The name satisfies Profile#is_valid_name.
+    pub fn profile_name(&self) -> String {
+        let label = match &self.issuer {
+            Some(issuer) => format!("{}-{}", issuer, self.account),
+            None => self.account.clone(),
+        };
+
+        Profile::sanitize_name(&label)
+    }
+
+    // Build a profile which is named with Self#profile_name.
+    //
+    // The issuer and the account are kept in the profile.
+    pub fn to_profile(&self) -> Profile {
+        let mut profile = Profile::new(&self.profile_name(), &self.secret);
+        if let Some(issuer) = &self.issuer {
+            profile.set_issuer(issuer);
+        }
+        profile.set_account(&self.account);
+        profile.set_type(self.profile_type);
+        profile.set_algorithm(self.algorithm);
+        profile.set_digits(self.digits);
+        profile.set_period(self.period);
+        profile.set_counter(self.counter);
+        profile
+    }
+}
+
+// Canonical otpauth URI
+//
+// algorithm, digits and period are written only when they are not default values.
+impl fmt::Display for KeyUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}/", SCHEME, self.profile_type)?;
+        if let Some(issuer) = &self.issuer {
+            write!(f, "{}:", encode(issuer))?;
+        }
+        write!(f, "{}?secret={}", encode(&self.account), self.secret)?;
+
+        if let Some(issuer) = &self.issuer {
+            write!(f, "&issuer={}", encode(issuer))?;
+        }
+        if !self.algorithm.is_default() {
+            write!(f, "&algorithm={}", self.algorithm)?;
+        }
+        if self.digits != totp::TOTP_DIGITS {
+            write!(f, "&digits={}", self.digits)?;
+        }
+        match self.profile_type {
+            ProfileType::Totp if self.period != totp::TIME_STEP => {
+                write!(f, "&period={}", self.period)
+            }
+            ProfileType::Hotp => write!(f, "&counter={}", self.counter),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Percent-encode a part of URI.
+fn encode(value: &str) -> String {
+    utf8_percent_encode(value, ENCODE_SET).to_string()
+}
+
+// Percent-decode a part of URI.
+// '+' is decoded to a space in the parameters part.
+fn decode(value: &str, is_query: bool) -> ParseResult<String> {
+    let value = if is_query {
+        value.replace('+', " ")
+    } else {
+        value.to_string()
+    };
+
+    match percent_decode_str(&value).decode_utf8() {
+        Ok(decoded) => Ok(decoded.to_string()),
+        Err(_) => Err(ValidationError::InvalidFormat(
+            "URI must be encoded in UTF-8.",
+        )),
+    }
+}
+
+// Normalize a base32 secret. Spaces and paddings are removed and letters are uppercased.
+pub fn normalize_secret(secret: &str) -> ParseResult<String> {
+    let secret: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    if secret.is_empty() {
+        return Err(ValidationError::Requires("Secret must be present."));
+    }
+    if base32::decode(base32::Alphabet::RFC4648 { padding: false }, &secret).is_none() {
+        return Err(ValidationError::InvalidFormat(
+            "Secret must be encoded in base32.",
+        ));
+    }
+
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_totp_uri() {
+        let uri = "otpauth://totp/Example:alice@google.com?secret=JBSWY3DPEHPK3PXP&issuer=Example";
+        let key_uri = KeyUri::parse(uri).unwrap();
+
+        assert_eq!(key_uri.profile_type, ProfileType::Totp);
+        assert_eq!(key_uri.issuer, Some("Example".to_string()));
+        assert_eq!(key_uri.account, "alice@google.com");
+        assert_eq!(key_uri.secret, "JBSWY3DPEHPK3PXP");
+        assert_eq!(key_uri.algorithm, Algorithm::Sha1);
+        assert_eq!(key_uri.digits, 6);
+        assert_eq!(key_uri.period, 30);
+    }
+
+    #[test]
+    fn parse_uri_with_all_parameters() {
+        let uri = "otpauth://totp/ACME%20Co:john.doe@email.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60";
+        let key_uri = KeyUri::parse(uri).unwrap();
+
+        assert_eq!(key_uri.issuer, Some("ACME Co".to_string()));
+        assert_eq!(key_uri.account, "john.doe@email.com");
+        assert_eq!(key_uri.algorithm, Algorithm::Sha256);
+        assert_eq!(key_uri.digits, 8);
+        assert_eq!(key_uri.period, 60);
+    }
+
+    #[test]
+    fn parse_hotp_uri() {
+        let uri = "otpauth://hotp/alice?secret=JBSWY3DPEHPK3PXP&counter=5";
+        let key_uri = KeyUri::parse(uri).unwrap();
+
+        assert_eq!(key_uri.profile_type, ProfileType::Hotp);
+        assert_eq!(key_uri.issuer, None);
+        assert_eq!(key_uri.counter, 5);
+    }
+
+    #[test]
+    fn parse_uri_with_encoded_colon_in_label() {
+        let uri = "otpauth://totp/Example%3Aalice?secret=JBSWY3DPEHPK3PXP";
+        let key_uri = KeyUri::parse(uri).unwrap();
+
+        assert_eq!(key_uri.issuer, Some("Example".to_string()));
+        assert_eq!(key_uri.account, "alice");
+    }
+
+    #[test]
+    fn parse_uri_with_issuer_only_in_parameter() {
+        let uri = "otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&issuer=Example";
+        let key_uri = KeyUri::parse(uri).unwrap();
+
+        assert_eq!(key_uri.issuer, Some("Example".to_string()));
+    }
+
+    #[test]
+    fn parse_uri_without_scheme() {
+        assert_eq!(
+            KeyUri::parse("https://example.com"),
+            Err(ValidationError::InvalidFormat(
+                "URI must start with otpauth://."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_uri_with_unsupported_type() {
+        assert_eq!(
+            KeyUri::parse("otpauth://steam/alice?secret=JBSWY3DPEHPK3PXP"),
+            Err(ValidationError::Unsupported("Type must be totp or hotp."))
+        );
+    }
+
+    #[test]
+    fn parse_uri_without_secret() {
+        assert_eq!(
+            KeyUri::parse("otpauth://totp/alice?issuer=Example"),
+            Err(ValidationError::Requires("Secret must be present."))
+        );
+    }
+
+    #[test]
+    fn parse_uri_with_invalid_secret() {
+        assert_eq!(
+            KeyUri::parse("otpauth://totp/alice?secret=189"),
+            Err(ValidationError::InvalidFormat(
+                "Secret must be encoded in base32."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_uri_with_unsupported_algorithm() {
+        assert_eq!(
+            KeyUri::parse("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&algorithm=MD5"),
+            Err(ValidationError::Unsupported(
+                "Algorithm must be SHA1, SHA256 or SHA512."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_uri_with_unsupported_parameter() {
+        assert_eq!(
+            KeyUri::parse("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&foo=bar"),
+            Err(ValidationError::Unsupported(
+                "URI contains an unsupported parameter."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_hotp_uri_without_counter() {
+        assert_eq!(
+            KeyUri::parse("otpauth://hotp/alice?secret=JBSWY3DPEHPK3PXP"),
+            Err(ValidationError::Requires(
+                "Counter must be present for hotp."
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_uri_with_different_issuers() {
+        assert_eq!(
+            KeyUri::parse("otpauth://totp/Foo:alice?secret=JBSWY3DPEHPK3PXP&issuer=Bar"),
+            Err(ValidationError::InvalidFormat(
+                "Issuer of the label and the parameter must be the same."
+            ))
+        );
+    }
+
+    #[test]
+    fn profile_name_from_issuer_and_account() {
+        let uri = "otpauth://totp/ACME%20Co:john.doe@email.com?secret=JBSWY3DPEHPK3PXP";
+        let profile = KeyUri::parse(uri).unwrap().to_profile();
+
+        assert_eq!(profile.get_name(), "ACME-Co-john-doe@ema");
+        assert_eq!(profile.is_vaild(), Ok(()));
+    }
+
+    #[test]
+    fn profile_name_from_short_account() {
+        let uri = "otpauth://totp/%E3%81%82?secret=JBSWY3DPEHPK3PXP";
+        let profile = KeyUri::parse(uri).unwrap().to_profile();
+
+        assert_eq!(profile.get_name(), "___");
+        assert_eq!(profile.is_vaild(), Ok(()));
+    }
+
+    #[test]
+    fn format_totp_uri() {
+        let profile = Profile::new("test", "jbswy3dpehpk3pxp");
+        let key_uri = KeyUri::from_profile(&profile).unwrap();
+
+        assert_eq!(
+            key_uri.to_string(),
+            "otpauth://totp/test?secret=JBSWY3DPEHPK3PXP"
+        );
+    }
+
+    #[test]
+    fn profile_keeps_issuer_and_account() {
+        let uri = "otpauth://totp/Example:alice@google.com?secret=JBSWY3DPEHPK3PXP&issuer=Example";
+        let profile = KeyUri::parse(uri).unwrap().to_profile();
+
+        assert_eq!(profile.get_issuer(), Some("Example"));
+        assert_eq!(profile.get_account(), Some("alice@google.com"));
+        assert_eq!(
+            KeyUri::from_profile(&profile).unwrap().to_string(),
+            "otpauth://totp/Example:alice%40google.com?secret=JBSWY3DPEHPK3PXP&issuer=Example"
+        );
+    }
+
+    #[test]
+    fn format_uri_with_non_default_parameters() {
+        let mut profile = Profile::new("test", "JBSWY3DPEHPK3PXP");
+        profile.set_algorithm(Algorithm::Sha512);
+        profile.set_digits(8);
+        profile.set_period(60);
+        let key_uri = KeyUri::from_profile(&profile).unwrap();
+
+        assert_eq!(
+            key_uri.to_string(),
+            "otpauth://totp/test?secret=JBSWY3DPEHPK3PXP&algorithm=SHA512&digits=8&period=60"
+        );
+    }
+
+    #[test]
+    fn format_uri_with_t0() {
+        let mut profile = Profile::new("test", "JBSWY3DPEHPK3PXP");
+        profile.set_t0(100);
+
+        assert_eq!(
+            KeyUri::from_profile(&profile).unwrap_err(),
+            ValidationError::Unsupported("T0 must be 0.")
+        );
+    }
+
+    #[test]
+    fn format_hotp_uri() {
+        let mut profile = Profile::new("test", "JBSWY3DPEHPK3PXP");
+        profile.set_type(ProfileType::Hotp);
+        profile.set_counter(3);
+        let key_uri = KeyUri::from_profile(&profile).unwrap();
+
+        assert_eq!(
+            key_uri.to_string(),
+            "otpauth://hotp/test?secret=JBSWY3DPEHPK3PXP&counter=3"
+        );
+    }
+
+    #[test]
+    fn format_uri_with_issuer() {
+        let uri =
+            "otpauth://totp/ACME%20Co:john%40email.com?secret=JBSWY3DPEHPK3PXP&issuer=ACME%20Co";
+        let key_uri = KeyUri::parse(uri).unwrap();
+
+        assert_eq!(key_uri.to_string(), uri);
+        assert_eq!(KeyUri::parse(&key_uri.to_string()).unwrap(), key_uri);
+    }
+}
@@ -0,0 +1,191 @@
+use super::config::{Profile, ValidationError};
+use super::import;
+use super::otpauth::KeyUri;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const OTPAUTH_SCHEME: &str = "otpauth://";
+// pass が暗号化したファイルの拡張子
+const GPG_EXTENSION: &str = "gpg";
+
+// An otpauth URI in an entry of password-store (pass-otp)
+//
+// The name is the path of the entry from the store without the extension. e.g. "web/github.com"
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    name: String,
+    uri: String,
+}
+
+// The default password-store. $PASSWORD_STORE_DIR or ~/.password-store
+pub fn default_store() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("PASSWORD_STORE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".password-store"))
+}
+
+// Find encrypted entries in a password-store and return their names.
+//
+// Hidden files and directories like .git are ignored. Names are sorted.
+pub fn find(store: &Path) -> Result<Vec<String>, String> {
+    let mut names = Vec::new();
+    find_in(store, "", &mut names)?;
+    names.sort();
+
+    Ok(names)
+}
+
+fn find_in(dir: &Path, prefix: &str, names: &mut Vec<String>) -> Result<(), String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return Err(format!("{}: {}", dir.display(), err)),
+    };
+
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(err) => return Err(err.to_string()),
+        };
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if !name.starts_with('.') => name,
+            _ => continue,
+        };
+
+        if path.is_dir() {
+            find_in(&path, &format!("{}{}/", prefix, file_name), names)?;
+        } else if path.extension().and_then(|ext| ext.to_str()) == Some(GPG_EXTENSION) {
+            let stem = &file_name[..file_name.len() - GPG_EXTENSION.len() - 1];
+            names.push(format!("{}{}", prefix, stem));
+        }
+    }
+
+    Ok(())
+}
+
+// Path of the encrypted file of an entry
+pub fn entry_path(store: &Path, name: &str) -> PathBuf {
+    store.join(format!("{}.{}", name, GPG_EXTENSION))
+}
+
+// Decrypt a file with the local gpg.
+//
+// gpg-agent asks the passphrase if it is needed.
+pub fn decrypt(path: &Path) -> Result<String, String> {
+    let output = match Command::new("gpg")
+        .args(["--quiet", "--yes", "--decrypt"])
+        .arg(path)
+        .stdin(Stdio::inherit())
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) => return Err(format!("failed to run gpg: {}", err)),
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return match stderr.lines().rev().find(|line| !line.trim().is_empty()) {
+            Some(line) => Err(format!("gpg failed: {}", line.trim())),
+            None => Err(format!("gpg failed: {}", output.status)),
+        };
+    }
+    match String::from_utf8(output.stdout) {
+        Ok(content) => Ok(content),
+        Err(_) => Err(String::from("The entry is not UTF-8 text.")),
+    }
+}
+
+// Pick out otpauth URI lines of a decrypted entry.
+pub fn parse(name: &str, content: &str) -> Vec<Entry> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| line.starts_with(OTPAUTH_SCHEME))
+        .map(|uri| Entry {
+            name: name.to_string(),
+            uri: uri.to_string(),
+        })
+        .collect()
+}
+
+impl import::Entry for Entry {
+    // The path of the entry
+    fn label(&self) -> String {
+        self.name.clone()
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the path of the entry. e.g. "web/github.com" -> "web-github-com"
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        let mut profile = KeyUri::parse(&self.uri)?.to_profile();
+        profile.set_name(&Profile::sanitize_name(&self.name));
+
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    #[test]
+    fn find_entries() {
+        let store = tempfile::tempdir().unwrap();
+        fs::create_dir_all(store.path().join("web/github.com")).unwrap();
+        fs::create_dir_all(store.path().join(".git")).unwrap();
+        fs::write(store.path().join("web/github.com/alice.gpg"), "").unwrap();
+        fs::write(store.path().join("email.gpg"), "").unwrap();
+        fs::write(store.path().join(".gpg-id"), "").unwrap();
+        fs::write(store.path().join(".git/config.gpg"), "").unwrap();
+        fs::write(store.path().join("notes.txt"), "").unwrap();
+
+        assert_eq!(
+            find(store.path()).unwrap(),
+            vec!["email", "web/github.com/alice"]
+        );
+        assert_eq!(
+            entry_path(store.path(), "email"),
+            store.path().join("email.gpg")
+        );
+    }
+
+    #[test]
+    fn find_in_missing_store() {
+        assert!(find(Path::new("/nonexistent/password-store")).is_err());
+    }
+
+    #[test]
+    fn parse_otpauth_lines() {
+        let content = "password\nlogin: alice\n  otpauth://totp/GitHub:alice?secret=JBSWY3DPEHPK3PXP&issuer=GitHub\n";
+        let entries = parse("web/github.com/alice", content);
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].label(), "web/github.com/alice");
+        let mut expected = Profile::new("web-github-com-alice", "JBSWY3DPEHPK3PXP");
+        expected.set_issuer("GitHub");
+        expected.set_account("alice");
+        assert_eq!(entries[0].to_profile(), Ok(expected));
+    }
+
+    #[test]
+    fn parse_without_otpauth() {
+        assert!(parse("email", "password\nlogin: alice\n").is_empty());
+    }
+
+    #[test]
+    fn invalid_uri() {
+        let entries = parse("broken", "otpauth://totp/broken?secret=189");
+
+        assert_eq!(
+            entries[0].to_profile(),
+            Err(ValidationError::InvalidFormat(
+                "Secret must be encoded in base32."
+            ))
+        );
+    }
+}
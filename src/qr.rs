@@ -0,0 +1,299 @@
+extern crate jpeg_decoder;
+extern crate png;
+extern crate qrcode;
+extern crate rqrr;
+
+use super::storage;
+use qrcode::render::{svg, unicode};
+use qrcode::{Color, QrCode};
+use std::fs;
+use std::path::Path;
+
+// PNG の 1 モジュールあたりのピクセル数
+const PNG_MODULE_SIZE: usize = 8;
+// PNG の余白 (モジュール数)
+const PNG_QUIET_ZONE: usize = 4;
+
+// Render data as a QR code with Unicode half block characters.
+//
+// Colors are inverted so that the code is readable in a terminal with a dark background.
+pub fn render_unicode(data: &str) -> Result<String, String> {
+    let code = encode(data)?;
+
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+// Render data as a QR code in SVG.
+pub fn render_svg(data: &str) -> Result<String, String> {
+    let code = encode(data)?;
+
+    Ok(code.render::<svg::Color>().min_dimensions(200, 200).build())
+}
+
+// Render data as a QR code in 8 bit grayscale PNG.
+pub fn render_png(data: &str) -> Result<Vec<u8>, String> {
+    let code = encode(data)?;
+    let width = code.width();
+    let colors = code.to_colors();
+
+    let side = (width + PNG_QUIET_ZONE * 2) * PNG_MODULE_SIZE;
+    let mut pixels = vec![0xff_u8; side * side];
+    for (i, color) in colors.iter().enumerate() {
+        if *color != Color::Dark {
+            continue;
+        }
+
+        let x = (i % width + PNG_QUIET_ZONE) * PNG_MODULE_SIZE;
+        let y = (i / width + PNG_QUIET_ZONE) * PNG_MODULE_SIZE;
+        for dy in 0..PNG_MODULE_SIZE {
+            let start = (y + dy) * side + x;
+            pixels[start..start + PNG_MODULE_SIZE].fill(0x00);
+        }
+    }
+
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, side as u32, side as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = match encoder.write_header() {
+        Ok(writer) => writer,
+        Err(err) => return Err(err.to_string()),
+    };
+    if let Err(err) = writer.write_image_data(&pixels) {
+        return Err(err.to_string());
+    }
+    if let Err(err) = writer.finish() {
+        return Err(err.to_string());
+    }
+
+    Ok(buffer)
+}
+
+// Write a rendered QR code to a file.
+//
+// The file is readable only by the user because the QR code holds the secret key.
+pub fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    storage::write_private(path, data).map_err(|err| err.to_string())
+}
+
+// Decode all of QR codes in a PNG or JPEG image file.
+//
+// It returns decoded contents, or error messages for QR codes that were found but couldn't be decoded.
+pub fn decode_image_file(path: &Path) -> Result<Vec<Result<String, String>>, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let image = if data.starts_with(PNG_SIGNATURE) {
+        load_png(&data)?
+    } else if data.starts_with(JPEG_SIGNATURE) {
+        load_jpeg(&data)?
+    } else {
+        return Err(String::from("The image must be PNG or JPEG"));
+    };
+
+    decode(&image)
+}
+
+// 8 bit グレースケールの画像
+struct GrayImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_SIGNATURE: &[u8] = b"\xff\xd8\xff";
+
+fn decode(image: &GrayImage) -> Result<Vec<Result<String, String>>, String> {
+    let mut prepared =
+        rqrr::PreparedImage::prepare_from_greyscale(image.width, image.height, |x, y| {
+            image.pixels[y * image.width + x]
+        });
+
+    let grids = prepared.detect_grids();
+    if grids.is_empty() {
+        return Err(String::from("No QR code is found in the image"));
+    }
+
+    Ok(grids
+        .iter()
+        .map(|grid| match grid.decode() {
+            Ok((_, content)) => Ok(content),
+            Err(err) => Err(err.to_string()),
+        })
+        .collect())
+}
+
+fn load_png(data: &[u8]) -> Result<GrayImage, String> {
+    let mut decoder = png::Decoder::new(data);
+    // palette and 16 bit images are converted to 8 bit channels
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = match decoder.read_info() {
+        Ok(reader) => reader,
+        Err(err) => return Err(err.to_string()),
+    };
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = match reader.next_frame(&mut buffer) {
+        Ok(info) => info,
+        Err(err) => return Err(err.to_string()),
+    };
+    let buffer = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Grayscale => buffer.to_vec(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks(2).map(|p| p[0]).collect(),
+        png::ColorType::Rgb => buffer.chunks(3).map(luma).collect(),
+        png::ColorType::Rgba => buffer.chunks(4).map(luma).collect(),
+        png::ColorType::Indexed => return Err(String::from("Unsupported PNG color type")),
+    };
+
+    Ok(GrayImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+fn load_jpeg(data: &[u8]) -> Result<GrayImage, String> {
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let buffer = match decoder.decode() {
+        Ok(buffer) => buffer,
+        Err(err) => return Err(err.to_string()),
+    };
+    let info = match decoder.info() {
+        Some(info) => info,
+        None => return Err(String::from("Broken JPEG image")),
+    };
+
+    let pixels = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => buffer,
+        jpeg_decoder::PixelFormat::L16 => buffer
+            .chunks(2)
+            .map(|p| (u16::from_ne_bytes([p[0], p[1]]) >> 8) as u8)
+            .collect(),
+        jpeg_decoder::PixelFormat::RGB24 => buffer.chunks(3).map(luma).collect(),
+        jpeg_decoder::PixelFormat::CMYK32 => buffer.chunks(4).map(cmyk_luma).collect(),
+    };
+
+    Ok(GrayImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+// Luminance of a RGB pixel (ITU-R BT.601)
+fn luma(rgb: &[u8]) -> u8 {
+    ((rgb[0] as u32 * 299 + rgb[1] as u32 * 587 + rgb[2] as u32 * 114) / 1000) as u8
+}
+
+// Luminance of a CMYK pixel
+fn cmyk_luma(cmyk: &[u8]) -> u8 {
+    let k = 255 - cmyk[3] as u32;
+    let rgb: Vec<u8> = cmyk[..3]
+        .iter()
+        .map(|c| ((255 - *c as u32) * k / 255) as u8)
+        .collect();
+    luma(&rgb)
+}
+
+fn encode(data: &str) -> Result<QrCode, String> {
+    match QrCode::new(data.as_bytes()) {
+        Ok(code) => Ok(code),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_unicode_uses_half_blocks() {
+        let rendered = render_unicode("otpauth://totp/test?secret=JBSWY3DPEHPK3PXP").unwrap();
+
+        assert!(rendered.contains('▀') || rendered.contains('▄'));
+        assert!(rendered.chars().all(|c| " ▀▄█\n".contains(c)));
+    }
+
+    #[test]
+    fn render_svg_document() {
+        let rendered = render_svg("otpauth://totp/test?secret=JBSWY3DPEHPK3PXP").unwrap();
+
+        assert!(rendered.starts_with("<?xml"));
+        assert!(rendered.contains("<svg"));
+    }
+
+    #[test]
+    fn render_png_signature() {
+        let rendered = render_png("otpauth://totp/test?secret=JBSWY3DPEHPK3PXP").unwrap();
+
+        assert_eq!(&rendered[..8], b"\x89PNG\r\n\x1a\n");
+    }
+
+    #[test]
+    fn decode_rendered_png() {
+        let uri = "otpauth://totp/test?secret=JBSWY3DPEHPK3PXP";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qr.png");
+        fs::write(&path, render_png(uri).unwrap()).unwrap();
+
+        let decoded = decode_image_file(&path).unwrap();
+        assert_eq!(decoded, vec![Ok(uri.to_string())]);
+    }
+
+    #[test]
+    fn decode_multiple_codes() {
+        let uris = [
+            "otpauth://totp/first?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/second?secret=GEZDGNBVGY3TQOJQ",
+        ];
+
+        // put 2 codes side by side with 4 modules quiet zone and 4 pixels per module
+        let codes: Vec<QrCode> = uris.iter().map(|uri| encode(uri).unwrap()).collect();
+        let size = codes[0].width() + 8;
+        let (width, height) = (size * 2 * 4, size * 4);
+        let mut pixels = vec![0xff_u8; width * height];
+        for (n, code) in codes.iter().enumerate() {
+            for (i, color) in code.to_colors().iter().enumerate() {
+                if *color != Color::Dark {
+                    continue;
+                }
+                let x = (n * size + i % code.width() + 4) * 4;
+                let y = (i / code.width() + 4) * 4;
+                for dy in 0..4 {
+                    pixels[(y + dy) * width + x..(y + dy) * width + x + 4].fill(0);
+                }
+            }
+        }
+
+        let mut decoded = decode(&GrayImage {
+            width,
+            height,
+            pixels,
+        })
+        .unwrap();
+        decoded.sort();
+        assert_eq!(
+            decoded,
+            vec![Ok(uris[0].to_string()), Ok(uris[1].to_string())]
+        );
+    }
+
+    #[test]
+    fn decode_not_image_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qr.txt");
+        fs::write(&path, "otpauth://totp/test?secret=JBSWY3DPEHPK3PXP").unwrap();
+
+        assert!(decode_image_file(&path).is_err());
+    }
+}
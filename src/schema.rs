@@ -0,0 +1,112 @@
+extern crate toml;
+
+// 設定ファイルの形式の version
+pub const VERSION: u32 = 2;
+
+// A step which upgrades the config from a version to the next one
+type Migration = fn(&mut toml::Table) -> Result<(), String>;
+
+// MIGRATIONS[n] upgrades version n to n + 1.
+const MIGRATIONS: [Migration; VERSION as usize] = [from_v0, from_v1];
+
+// The version of the config. Files without the version key are version 0.
+//
+// It fails if the file was written by a newer mfa-cli.
+pub fn version(contents: &str) -> Result<u32, String> {
+    version_of(&parse(contents)?)
+}
+
+// Upgrade the config to the current version.
+//
+// It returns the contents as they are if they are already current.
+pub fn migrate(contents: &str) -> Result<String, String> {
+    let mut table = parse(contents)?;
+    let version = version_of(&table)?;
+    if version == VERSION {
+        return Ok(contents.to_string());
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut table)?;
+    }
+    table.insert(
+        String::from("version"),
+        toml::Value::Integer(VERSION.into()),
+    );
+
+    match toml::to_string(&table) {
+        Ok(contents) => Ok(contents),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse(contents: &str) -> Result<toml::Table, String> {
+    match contents.parse::<toml::Table>() {
+        Ok(table) => Ok(table),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn version_of(table: &toml::Table) -> Result<u32, String> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(toml::Value::Integer(version)) if 0 <= *version => *version as u64,
+        Some(_) => return Err(String::from("Version must be a positive integer.")),
+    };
+
+    if u64::from(VERSION) < version {
+        return Err(format!(
+            "The profile file was written by a newer mfa-cli (version {}). This mfa-cli supports up to version {}. Please upgrade mfa-cli.",
+            version, VERSION
+        ));
+    }
+    Ok(version as u32)
+}
+
+// version 0 は version キーがないだけで中身は同じ
+fn from_v0(_table: &mut toml::Table) -> Result<(), String> {
+    Ok(())
+}
+
+// version 2 はプロファイルのメタデータを追加した。
+// 古い mfa-cli がメタデータを落として書き戻さないように version を上げている
+fn from_v1(_table: &mut toml::Table) -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V0: &str = "[[profiles]]\nname = \"test\"\nsecret = \"JBSWY3DPEHPK3PXP\"\n";
+
+    #[test]
+    fn migrate_from_v0() {
+        assert_eq!(version(V0), Ok(0));
+
+        let migrated = migrate(V0).unwrap();
+        assert_eq!(version(&migrated), Ok(VERSION));
+        assert!(migrated.contains("secret = \"JBSWY3DPEHPK3PXP\""));
+    }
+
+    #[test]
+    fn migrate_current_as_it_is() {
+        let current = format!("version = {}\n{}", VERSION, V0);
+
+        assert_eq!(migrate(&current), Ok(current));
+    }
+
+    #[test]
+    fn newer_version() {
+        let newer = format!("version = {}\n{}", VERSION + 1, V0);
+
+        assert!(version(&newer).unwrap_err().contains("newer mfa-cli"));
+        assert!(migrate(&newer).is_err());
+    }
+
+    #[test]
+    fn broken_version() {
+        assert!(version("version = \"1\"\n").is_err());
+        assert!(version("version = -1\n").is_err());
+    }
+}
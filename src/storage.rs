@@ -0,0 +1,270 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// An advisory lock of the profile file
+//
+// The lock file is separated from the profile file
+// because the profile file is replaced by rename.
+// The lock is released when it is dropped.
+#[derive(Debug)]
+pub(crate) struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    // Wait until the lock is acquired exclusively.
+    pub(crate) fn acquire(path: &Path) -> Result<Self, String> {
+        let file = match private_options().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(err) => return Err(format!("failed to open {}: {}", path.display(), err)),
+        };
+        if let Err(err) = flock(&file) {
+            return Err(format!("failed to lock {}: {}", path.display(), err));
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(unix)]
+fn flock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    loop {
+        // SAFETY: the descriptor is owned by the file and open while it is used
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn flock(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+// Replace the contents of the file atomically.
+//
+// The data is written to a temporary file in the same directory, synced and renamed,
+// so that the file is never truncated by a crash.
+// The file is readable and writable only by the user.
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), String> {
+    let temp_path = temp_path(path);
+    let result = write_temp(&temp_path, data).and_then(|_| fs::rename(&temp_path, path));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err.to_string());
+    }
+
+    // rename を永続化するためにディレクトリも同期する
+    match sync_dir(path) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()))
+}
+
+fn write_temp(temp_path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = private_options()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+// Write a file which holds secrets, e.g. a QR code of a profile.
+// The file is readable and writable only by the user even if it already exists.
+pub(crate) fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = private_options().write(true).truncate(true).open(path)?;
+    // 既存のファイルは作成時のモードが使われないので書く前に絞る
+    make_private(path)?;
+    file.write_all(data)
+}
+
+// Options to create a file only for the user
+fn private_options() -> OpenOptions {
+    let mut options = OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+}
+
+// Create a directory only for the user with its parents.
+pub(crate) fn create_private_dir(path: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+// The reason of the exposure which can't be fixed by chmod
+pub(crate) const FOREIGN_OWNER: &str = "is owned by another user";
+
+// Why the file can be read by other users. None if it is private.
+#[cfg(unix)]
+pub(crate) fn exposure(path: &Path) -> io::Result<Option<&'static str>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let meta = path.metadata()?;
+    // SAFETY: geteuid has no side effects
+    if meta.uid() != unsafe { libc::geteuid() } {
+        return Ok(Some(FOREIGN_OWNER));
+    }
+    if meta.mode() & 0o077 != 0 {
+        return Ok(Some("is accessible by group or other users"));
+    }
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn exposure(_path: &Path) -> io::Result<Option<&'static str>> {
+    Ok(None)
+}
+
+// Make the file or the directory private. A file gets 0600 and a directory gets 0700.
+#[cfg(unix)]
+pub(crate) fn make_private(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = if path.is_dir() { 0o700 } else { 0o600 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn make_private(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+// ディレクトリを開いて同期できない環境では何もしない
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn write_atomic_replaces_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile");
+        fs::write(&path, "old contents which are longer").unwrap();
+
+        write_atomic(&path, b"new").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_makes_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(exposure(&path).unwrap().is_some());
+
+        write_atomic(&path, b"new").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(exposure(&path).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_private_makes_private_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let new_path = dir.path().join("new.png");
+        let old_path = dir.path().join("old.png");
+        fs::write(&old_path, "old contents").unwrap();
+        fs::set_permissions(&old_path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&new_path, b"new").unwrap();
+        write_private(&old_path, b"new").unwrap();
+
+        for path in [new_path, old_path] {
+            assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn make_private_dir_and_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("mfa-cli");
+        create_private_dir(&sub).unwrap();
+        assert_eq!(exposure(&sub).unwrap(), None);
+
+        fs::set_permissions(&sub, fs::Permissions::from_mode(0o755)).unwrap();
+        make_private(&sub).unwrap();
+        let mode = fs::metadata(&sub).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profile.lock");
+        let events = Arc::new(Mutex::new(Vec::new()));
+
+        let lock = Lock::acquire(&path).unwrap();
+        let waiter = {
+            let path = path.clone();
+            let events = Arc::clone(&events);
+            thread::spawn(move || {
+                let _lock = Lock::acquire(&path).unwrap();
+                events.lock().unwrap().push("waiter");
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        events.lock().unwrap().push("holder");
+        drop(lock);
+        waiter.join().unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec!["holder", "waiter"]);
+    }
+}
@@ -1,26 +1,77 @@
+use super::algorithm::Algorithm;
 use super::hotp;
 use byteorder::{BigEndian, WriteBytesExt};
 use std::time::SystemTime;
 
-const TIME_STEP: u64 = 30;
-const TOTP_DIGITS: u8 = 6;
+pub const TIME_STEP: u64 = 30;
+pub const TOTP_DIGITS: u8 = 6;
 
 // TOTP を現在時刻から計算する
-pub fn totp(secret: &[u8]) -> Result<String, String> {
+//
+// period is the time step in seconds (X) and t0 is the Unix time to start counting time steps (T0).
+pub fn totp(
+    secret: &[u8],
+    digits: u8,
+    period: u64,
+    t0: u64,
+    algorithm: Algorithm,
+) -> Result<String, String> {
     match current_time() {
-        Ok(current_time) => gen_totp(secret, current_time, TOTP_DIGITS),
+        Ok(current_time) => gen_totp(secret, current_time, digits, period, t0, algorithm),
+        Err(err) => Err(err),
+    }
+}
+
+// Steam Guard のコードを現在時刻から計算する
+pub fn steam(secret: &[u8], period: u64, t0: u64, algorithm: Algorithm) -> Result<String, String> {
+    match current_time() {
+        Ok(current_time) => gen_steam(secret, current_time, period, t0, algorithm),
         Err(err) => Err(err),
     }
 }
 
 // TOTP を任意の時刻で計算する
-fn gen_totp(secret: &[u8], time: u64, digits: u8) -> Result<String, String> {
-    let t = time / TIME_STEP;
+fn gen_totp(
+    secret: &[u8],
+    time: u64,
+    digits: u8,
+    period: u64,
+    t0: u64,
+    algorithm: Algorithm,
+) -> Result<String, String> {
+    let byte_t = time_counter(time, period, t0)?;
+
+    hotp::hotp(secret, &byte_t, digits, algorithm)
+}
+
+// Steam Guard のコードを任意の時刻で計算する
+fn gen_steam(
+    secret: &[u8],
+    time: u64,
+    period: u64,
+    t0: u64,
+    algorithm: Algorithm,
+) -> Result<String, String> {
+    let byte_t = time_counter(time, period, t0)?;
+
+    hotp::steam(secret, &byte_t, algorithm)
+}
+
+// 時刻から HOTP のカウンター (T) を計算する
+fn time_counter(time: u64, period: u64, t0: u64) -> Result<Vec<u8>, String> {
+    if period == 0 {
+        return Err(String::from("The period must be greater than 0"));
+    }
+    if time < t0 {
+        return Err(format!("The time is before T0: {}", t0));
+    }
+
+    let t = (time - t0) / period;
 
     let mut byte_t = Vec::new();
     byte_t.write_u64::<BigEndian>(t).unwrap();
 
-    hotp::hotp(secret, &byte_t, digits)
+    Ok(byte_t)
 }
 
 // UNIX time からの経過秒数を返す
@@ -52,40 +103,318 @@ mod tests {
     // | 6 | 20000000000 |  2603-10-11  | 0000000027BC86AA | 65353130 |  SHA1  |
     // |   |             |   11:33:20   |                  |          |        |
     // +---+-------------+--------------+------------------+----------+--------+
+    // | 1 |      59     |  1970-01-01  | 0000000000000001 | 46119246 | SHA256 |
+    // | 2 |  1111111109 |  2005-03-18  | 00000000023523EC | 68084774 | SHA256 |
+    // | 3 |  1111111111 |  2005-03-18  | 00000000023523ED | 67062674 | SHA256 |
+    // | 4 |  1234567890 |  2009-02-13  | 000000000273EF07 | 91819424 | SHA256 |
+    // | 5 |  2000000000 |  2033-05-18  | 0000000003F940AA | 90698825 | SHA256 |
+    // | 6 | 20000000000 |  2603-10-11  | 0000000027BC86AA | 77737706 | SHA256 |
+    // +---+-------------+--------------+------------------+----------+--------+
+    // | 1 |      59     |  1970-01-01  | 0000000000000001 | 90693936 | SHA512 |
+    // | 2 |  1111111109 |  2005-03-18  | 00000000023523EC | 25091201 | SHA512 |
+    // | 3 |  1111111111 |  2005-03-18  | 00000000023523ED | 99943326 | SHA512 |
+    // | 4 |  1234567890 |  2009-02-13  | 000000000273EF07 | 93441116 | SHA512 |
+    // | 5 |  2000000000 |  2033-05-18  | 0000000003F940AA | 38618901 | SHA512 |
+    // | 6 | 20000000000 |  2603-10-11  | 0000000027BC86AA | 47863826 | SHA512 |
+    // +---+-------------+--------------+------------------+----------+--------+
 
     #[test]
     fn rfc_6238_1() {
-        let totp = gen_totp(b"12345678901234567890", 59, 8).unwrap();
+        let totp = gen_totp(
+            b"12345678901234567890",
+            59,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
         assert_eq!(totp, "94287082");
     }
 
     #[test]
     fn rfc_6238_2() {
-        let totp = gen_totp(b"12345678901234567890", 1_111_111_109, 8).unwrap();
+        let totp = gen_totp(
+            b"12345678901234567890",
+            1_111_111_109,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
         assert_eq!(totp, "07081804");
     }
 
     #[test]
     fn rfc_6238_3() {
-        let totp = gen_totp(b"12345678901234567890", 1_111_111_111, 8).unwrap();
+        let totp = gen_totp(
+            b"12345678901234567890",
+            1_111_111_111,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
         assert_eq!(totp, "14050471");
     }
 
     #[test]
     fn rfc_6238_4() {
-        let totp = gen_totp(b"12345678901234567890", 1_234_567_890, 8).unwrap();
+        let totp = gen_totp(
+            b"12345678901234567890",
+            1_234_567_890,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
         assert_eq!(totp, "89005924");
     }
 
     #[test]
     fn rfc_6238_5() {
-        let totp = gen_totp(b"12345678901234567890", 2_000_000_000, 8).unwrap();
+        let totp = gen_totp(
+            b"12345678901234567890",
+            2_000_000_000,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
         assert_eq!(totp, "69279037");
     }
 
     #[test]
     fn rfc_6238_6() {
-        let totp = gen_totp(b"12345678901234567890", 20_000_000_000, 8).unwrap();
+        let totp = gen_totp(
+            b"12345678901234567890",
+            20_000_000_000,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
         assert_eq!(totp, "65353130");
     }
+
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+
+    #[test]
+    fn rfc_6238_sha256_1() {
+        let totp = gen_totp(SEED_SHA256, 59, 8, TIME_STEP, 0, Algorithm::Sha256).unwrap();
+        assert_eq!(totp, "46119246");
+    }
+
+    #[test]
+    fn rfc_6238_sha256_2() {
+        let totp = gen_totp(
+            SEED_SHA256,
+            1_111_111_109,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha256,
+        )
+        .unwrap();
+        assert_eq!(totp, "68084774");
+    }
+
+    #[test]
+    fn rfc_6238_sha256_3() {
+        let totp = gen_totp(
+            SEED_SHA256,
+            1_111_111_111,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha256,
+        )
+        .unwrap();
+        assert_eq!(totp, "67062674");
+    }
+
+    #[test]
+    fn rfc_6238_sha256_4() {
+        let totp = gen_totp(
+            SEED_SHA256,
+            1_234_567_890,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha256,
+        )
+        .unwrap();
+        assert_eq!(totp, "91819424");
+    }
+
+    #[test]
+    fn rfc_6238_sha256_5() {
+        let totp = gen_totp(
+            SEED_SHA256,
+            2_000_000_000,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha256,
+        )
+        .unwrap();
+        assert_eq!(totp, "90698825");
+    }
+
+    #[test]
+    fn rfc_6238_sha256_6() {
+        let totp = gen_totp(
+            SEED_SHA256,
+            20_000_000_000,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha256,
+        )
+        .unwrap();
+        assert_eq!(totp, "77737706");
+    }
+
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn rfc_6238_sha512_1() {
+        let totp = gen_totp(SEED_SHA512, 59, 8, TIME_STEP, 0, Algorithm::Sha512).unwrap();
+        assert_eq!(totp, "90693936");
+    }
+
+    #[test]
+    fn rfc_6238_sha512_2() {
+        let totp = gen_totp(
+            SEED_SHA512,
+            1_111_111_109,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha512,
+        )
+        .unwrap();
+        assert_eq!(totp, "25091201");
+    }
+
+    #[test]
+    fn rfc_6238_sha512_3() {
+        let totp = gen_totp(
+            SEED_SHA512,
+            1_111_111_111,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha512,
+        )
+        .unwrap();
+        assert_eq!(totp, "99943326");
+    }
+
+    #[test]
+    fn rfc_6238_sha512_4() {
+        let totp = gen_totp(
+            SEED_SHA512,
+            1_234_567_890,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha512,
+        )
+        .unwrap();
+        assert_eq!(totp, "93441116");
+    }
+
+    #[test]
+    fn rfc_6238_sha512_5() {
+        let totp = gen_totp(
+            SEED_SHA512,
+            2_000_000_000,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha512,
+        )
+        .unwrap();
+        assert_eq!(totp, "38618901");
+    }
+
+    #[test]
+    fn rfc_6238_sha512_6() {
+        let totp = gen_totp(
+            SEED_SHA512,
+            20_000_000_000,
+            8,
+            TIME_STEP,
+            0,
+            Algorithm::Sha512,
+        )
+        .unwrap();
+        assert_eq!(totp, "47863826");
+    }
+
+    #[test]
+    fn totp_with_period() {
+        // T = 59 / 60 = 0
+        let totp = gen_totp(b"12345678901234567890", 59, 6, 60, 0, Algorithm::Sha1).unwrap();
+        assert_eq!(totp, "755224");
+    }
+
+    #[test]
+    fn totp_with_t0() {
+        // T = (1_000_000_059 - 1_000_000_000) / 30 = 1
+        let totp = gen_totp(
+            b"12345678901234567890",
+            1_000_000_059,
+            8,
+            TIME_STEP,
+            1_000_000_000,
+            Algorithm::Sha1,
+        )
+        .unwrap();
+        assert_eq!(totp, "94287082");
+    }
+
+    #[test]
+    fn totp_before_t0() {
+        let totp = gen_totp(
+            b"12345678901234567890",
+            59,
+            6,
+            TIME_STEP,
+            60,
+            Algorithm::Sha1,
+        );
+        assert!(totp.is_err());
+    }
+
+    #[test]
+    fn totp_with_zero_period() {
+        let totp = gen_totp(b"12345678901234567890", 59, 6, 0, 0, Algorithm::Sha1);
+        assert!(totp.is_err());
+    }
+
+    #[test]
+    fn steam_1() {
+        let code = gen_steam(b"12345678901234567890", 59, TIME_STEP, 0, Algorithm::Sha1).unwrap();
+        assert_eq!(code, "PV9M4");
+    }
+
+    #[test]
+    fn steam_2() {
+        let code = gen_steam(
+            b"12345678901234567890",
+            1_111_111_109,
+            TIME_STEP,
+            0,
+            Algorithm::Sha1,
+        )
+        .unwrap();
+        assert_eq!(code, "PY4YB");
+    }
 }
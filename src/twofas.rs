@@ -0,0 +1,269 @@
+extern crate base64;
+extern crate serde_json;
+
+use super::algorithm::Algorithm;
+use super::config::{Profile, ProfileType, ValidationError};
+use super::crypto;
+use super::import;
+use super::otpauth;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+
+// 2FAS の PBKDF2 の反復回数
+const ITERATIONS: u32 = 10000;
+
+// 2FAS の "Export" で出力される .2fas ファイル
+//
+// When the backup is protected with a password, services is empty
+// and servicesEncrypted holds "ciphertext:salt:nonce" in base64.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Backup {
+    #[serde(default)]
+    services: Vec<Service>,
+    services_encrypted: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Service {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    secret: String,
+    #[serde(default)]
+    otp: Otp,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Otp {
+    account: Option<String>,
+    issuer: Option<String>,
+    algorithm: Option<String>,
+    digits: Option<u8>,
+    period: Option<u64>,
+    counter: Option<u64>,
+    token_type: Option<String>,
+}
+
+impl Backup {
+    // Parse a 2FAS backup.
+    pub fn parse(data: &str) -> Result<Self, String> {
+        match serde_json::from_str(data) {
+            Ok(backup) => Ok(backup),
+            Err(_) => Err(String::from("It is not a 2FAS backup.")),
+        }
+    }
+
+    // Whether the services are encrypted with a password.
+    pub fn is_encrypted(&self) -> bool {
+        self.services_encrypted.is_some()
+    }
+
+    // Get the services.
+    //
+    // The password is required only when the backup is encrypted.
+    pub fn services(self, password: Option<&str>) -> Result<Vec<Service>, String> {
+        let encrypted = match self.services_encrypted {
+            Some(encrypted) => encrypted,
+            None => return Ok(self.services),
+        };
+        let password = match password {
+            Some(password) => password,
+            None => return Err(String::from("The password is required.")),
+        };
+
+        let plaintext = decrypt(&encrypted, password)?;
+        match serde_json::from_slice(&plaintext) {
+            Ok(services) => Ok(services),
+            Err(_) => Err(String::from("The backup is broken.")),
+        }
+    }
+}
+
+// Decrypt "ciphertext:salt:nonce" with a key derived by PBKDF2-HMAC-SHA256.
+fn decrypt(encrypted: &str, password: &str) -> Result<Vec<u8>, String> {
+    let parts: Vec<Vec<u8>> = match encrypted
+        .split(':')
+        .map(|part| BASE64.decode(part))
+        .collect()
+    {
+        Ok(parts) => parts,
+        Err(_) => return Err(String::from("The backup is broken.")),
+    };
+    let (ciphertext, salt, nonce) = match parts.as_slice() {
+        [ciphertext, salt, nonce] => (ciphertext, salt, nonce),
+        _ => return Err(String::from("The backup is broken.")),
+    };
+
+    let key = crypto::pbkdf2_sha256(password, salt, ITERATIONS);
+    match crypto::aes_gcm_decrypt(&key, nonce, ciphertext) {
+        Ok(plaintext) => Ok(plaintext),
+        Err(_) => Err(String::from("The password is incorrect.")),
+    }
+}
+
+impl Service {
+    // The account of the service. It falls back to an empty string.
+    fn account(&self) -> &str {
+        match &self.otp.account {
+            Some(account) => account.trim(),
+            None => "",
+        }
+    }
+
+    // The issuer of the service. It is the issuer in otp or the name of the service.
+    fn issuer(&self) -> &str {
+        match &self.otp.issuer {
+            Some(issuer) if !issuer.is_empty() => issuer.trim(),
+            _ => self.name.trim(),
+        }
+    }
+}
+
+impl import::Entry for Service {
+    // A label to show the service without the secret. "Issuer:Account" or "Issuer"
+    fn label(&self) -> String {
+        if self.account().is_empty() {
+            return self.issuer().to_string();
+        }
+
+        format!("{}:{}", self.issuer(), self.account())
+    }
+
+    // Build a profile.
+    //
+    // The name is derived from the issuer and the account like otpauth URI.
+    fn to_profile(&self) -> Result<Profile, ValidationError> {
+        let profile_type = match &self.otp.token_type {
+            Some(token_type) => match token_type.parse::<ProfileType>() {
+                Ok(profile_type) => profile_type,
+                Err(_) => {
+                    return Err(ValidationError::Unsupported(
+                        "Token type must be TOTP, HOTP or STEAM.",
+                    ))
+                }
+            },
+            None => ProfileType::Totp,
+        };
+        let algorithm = match &self.otp.algorithm {
+            Some(algorithm) => match algorithm.parse::<Algorithm>() {
+                Ok(algorithm) => algorithm,
+                Err(_) => {
+                    return Err(ValidationError::Unsupported(
+                        "Algorithm must be SHA1, SHA256 or SHA512.",
+                    ))
+                }
+            },
+            None => Algorithm::Sha1,
+        };
+        if self.secret.is_empty() {
+            return Err(ValidationError::Requires("Secret must be present."));
+        }
+
+        let name = import::profile_name(self.issuer(), self.account());
+        let mut profile = Profile::new(&name, &otpauth::normalize_secret(&self.secret)?);
+        profile.set_issuer(self.issuer());
+        profile.set_account(self.account());
+        profile.set_type(profile_type);
+        profile.set_algorithm(algorithm);
+        import::set_digits(&mut profile, self.otp.digits);
+        if profile_type == ProfileType::Hotp {
+            profile.set_counter(self.otp.counter.unwrap_or(0));
+        } else if let Some(period) = self.otp.period {
+            profile.set_period(period);
+        }
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::Entry as _;
+
+    const PLAIN: &str = include_str!("../tests/fixtures/twofas-plain.2fas");
+    // encrypted with the password "test"
+    const ENCRYPTED: &str = include_str!("../tests/fixtures/twofas-encrypted.2fas");
+
+    #[test]
+    fn parse_plain_backup() {
+        let backup = Backup::parse(PLAIN).unwrap();
+        assert!(!backup.is_encrypted());
+
+        let profiles: Vec<Result<Profile, ValidationError>> = backup
+            .services(None)
+            .unwrap()
+            .iter()
+            .map(|service| service.to_profile())
+            .collect();
+
+        let mut totp = Profile::new("Example-alice@exampl", "JBSWY3DPEHPK3PXP");
+        totp.set_issuer("Example");
+        totp.set_account("alice@example.com");
+        totp.set_algorithm(Algorithm::Sha256);
+        totp.set_digits(8);
+        totp.set_period(60);
+        let mut hotp = Profile::new("bob", "GEZDGNBVGY3TQOJQ");
+        hotp.set_issuer("bob");
+        hotp.set_type(ProfileType::Hotp);
+        hotp.set_counter(5);
+        let mut steam = Profile::new("Steam-carol", "JBSWY3DPEHPK3PXP");
+        steam.set_issuer("Steam");
+        steam.set_account("carol");
+        steam.set_type(ProfileType::Steam);
+
+        assert_eq!(
+            profiles,
+            vec![
+                Ok(totp),
+                Ok(hotp),
+                Ok(steam),
+                Err(ValidationError::Unsupported(
+                    "Algorithm must be SHA1, SHA256 or SHA512."
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_encrypted_backup() {
+        let backup = Backup::parse(ENCRYPTED).unwrap();
+        assert!(backup.is_encrypted());
+
+        let labels: Vec<String> = backup
+            .services(Some("test"))
+            .unwrap()
+            .iter()
+            .map(|service| service.label())
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Example:alice@example.com",
+                "bob",
+                "Steam:carol",
+                "Example:dave"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_with_wrong_password() {
+        assert_eq!(
+            Backup::parse(ENCRYPTED)
+                .unwrap()
+                .services(Some("wrong"))
+                .unwrap_err(),
+            "The password is incorrect."
+        );
+        assert_eq!(
+            Backup::parse(ENCRYPTED)
+                .unwrap()
+                .services(None)
+                .unwrap_err(),
+            "The password is required."
+        );
+    }
+}